@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::PROGRAM_START;
+use crate::instr::{parse_instruction, parse_number};
+
+// Assembler for the classic mnemonics `Instruction` parses and prints, so the
+// output of `disasm::disassemble` (Syntax::Classic) assembles back to the
+// same bytes. On top of the instructions a source line can hold:
+//
+//     name:                   label for the address of the next byte
+//     :const NAME 0x10        named number
+//     :alias counter V3       named register
+//     DB 0x01, 2, 0b11        data bytes
+//     SPRITE "XX..XX..", "..XX..XX"
+//                             sprite rows, X/#/1 set a pixel, ./0 clear it
+//     ; comment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    // both 1-based
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// a piece of a source line with the column it starts at
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+enum Statement<'a> {
+    Instr {
+        mnemonic: Token<'a>,
+        operands: Vec<Token<'a>>,
+    },
+    Bytes(Vec<Token<'a>>),
+    Sprite(Vec<Token<'a>>),
+}
+
+struct Line<'a> {
+    line: usize,
+    addr: usize,
+    statement: Statement<'a>,
+}
+
+const RESERVED: [&str; 9] = ["I", "DT", "ST", "K", "F", "HF", "R", "B", "[I]"];
+
+fn is_register(s: &str) -> bool {
+    let upper = s.to_ascii_uppercase();
+    upper.len() == 2 && upper.starts_with('V') && upper[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !is_register(s)
+        && !RESERVED.contains(&s.to_ascii_uppercase().as_str())
+}
+
+// split on a separator, keeping track of where each trimmed piece starts
+fn split_tokens<'a>(text: &'a str, column: usize, sep: impl Fn(char) -> bool) -> Vec<Token<'a>> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        if i == text.len() || sep(c) {
+            let piece = &text[start..i];
+            let trimmed = piece.trim_start();
+            let lead = piece.len() - trimmed.len();
+            let trimmed = trimmed.trim_end();
+            if !trimmed.is_empty() {
+                tokens.push(Token {
+                    text: trimmed,
+                    column: column + start + lead,
+                });
+            }
+            start = i + c.len_utf8();
+        }
+    }
+    tokens
+}
+
+struct Assembler<'a> {
+    labels: HashMap<&'a str, usize>,
+    consts: HashMap<&'a str, u16>,
+    aliases: HashMap<&'a str, &'a str>,
+}
+
+fn error(line: usize, column: usize, message: impl Into<String>) -> AsmError {
+    AsmError {
+        line,
+        column,
+        message: message.into(),
+    }
+}
+
+impl<'a> Assembler<'a> {
+    fn number(&self, line: usize, token: &Token) -> Result<u16, AsmError> {
+        if let Some(val) = self.consts.get(token.text) {
+            return Ok(*val);
+        }
+        if let Some(addr) = self.labels.get(token.text) {
+            return Ok(*addr as u16);
+        }
+        if is_identifier(token.text) {
+            return Err(error(
+                line,
+                token.column,
+                format!("unknown name {}", token.text),
+            ));
+        }
+        parse_number(token.text).map_err(|err| error(line, token.column, err))
+    }
+
+    // replace aliases, labels and constants so `Instruction` can parse it
+    fn resolve(&self, line: usize, token: &Token<'a>) -> Result<String, AsmError> {
+        if let Some(reg) = self.aliases.get(token.text) {
+            return Ok(reg.to_string());
+        }
+        if is_identifier(token.text) || token.text.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(format!("0x{:x}", self.number(line, token)?));
+        }
+        Ok(token.text.to_string())
+    }
+
+    fn sprite_row(line: usize, token: &Token) -> Result<u8, AsmError> {
+        let bits = token
+            .text
+            .strip_prefix('"')
+            .and_then(|t| t.strip_suffix('"'))
+            .ok_or(error(line, token.column, "sprite rows are quoted strings"))?;
+        if bits.len() > 8 {
+            return Err(error(
+                line,
+                token.column,
+                "sprite rows are at most 8 pixels",
+            ));
+        }
+        let mut row = 0;
+        for (i, c) in bits.chars().enumerate() {
+            let bit = match c {
+                'X' | 'x' | '#' | '1' => 1,
+                '.' | '0' | ' ' => 0,
+                _ => {
+                    let msg = format!("invalid sprite pixel {:?}", c);
+                    return Err(error(line, token.column + 1 + i, msg));
+                }
+            };
+            row |= bit << (7 - i);
+        }
+        Ok(row)
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler {
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+    };
+
+    // pass 1: labels, directives and the address of every statement
+    let mut lines = Vec::new();
+    let mut addr = PROGRAM_START;
+    for (line_idx, raw) in source.lines().enumerate() {
+        let line = line_idx + 1;
+        let mut text = raw.split(';').next().unwrap_or("");
+        let mut column = 1;
+
+        // any number of labels in front of the statement
+        while let Some((label, rest)) = text.split_once(':') {
+            let label_tokens = split_tokens(label, column, char::is_whitespace);
+            let [name] = label_tokens.as_slice() else {
+                break;
+            };
+            if !is_identifier(name.text) {
+                break;
+            }
+            if asm.labels.insert(name.text, addr).is_some() {
+                return Err(error(
+                    line,
+                    name.column,
+                    format!("duplicate label {}", name.text),
+                ));
+            }
+            column += label.len() + 1;
+            text = rest;
+        }
+
+        let tokens = split_tokens(text, column, char::is_whitespace);
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+        let rest_column = first.column + first.text.len();
+        let rest = &text[rest_column - column..];
+        let statement = match first.text.to_ascii_uppercase().as_str() {
+            ":CONST" | ":ALIAS" => {
+                let [_, name, val] = tokens.as_slice() else {
+                    return Err(error(
+                        line,
+                        first.column,
+                        format!("{} expects a name and a value", first.text),
+                    ));
+                };
+                if !is_identifier(name.text) {
+                    return Err(error(
+                        line,
+                        name.column,
+                        format!("invalid name {}", name.text),
+                    ));
+                }
+                if first.text.eq_ignore_ascii_case(":const") {
+                    let val = asm.number(line, val)?;
+                    asm.consts.insert(name.text, val);
+                } else {
+                    if !is_register(val.text) {
+                        return Err(error(
+                            line,
+                            val.column,
+                            format!("{} is not a register", val.text),
+                        ));
+                    }
+                    asm.aliases.insert(name.text, val.text);
+                }
+                continue;
+            }
+            "DB" => Statement::Bytes(split_tokens(rest, rest_column, |c| c == ',')),
+            "SPRITE" => Statement::Sprite(split_tokens(rest, rest_column, |c| c == ',')),
+            _ => Statement::Instr {
+                mnemonic: *first,
+                operands: split_tokens(rest, rest_column, |c| c == ','),
+            },
+        };
+        let size = match &statement {
+            // F000 NNNN is the only 4-byte instruction
+            Statement::Instr { mnemonic, .. } if mnemonic.text.eq_ignore_ascii_case("LDL") => 4,
+            Statement::Instr { .. } => 2,
+            Statement::Bytes(bytes) | Statement::Sprite(bytes) => bytes.len(),
+        };
+        lines.push(Line {
+            line,
+            addr,
+            statement,
+        });
+        addr += size;
+    }
+
+    // pass 2: encode with every name known
+    let mut out = Vec::new();
+    for Line {
+        line,
+        addr,
+        statement,
+    } in &lines
+    {
+        let line = *line;
+        if *addr > 0xFFFF {
+            return Err(error(
+                line,
+                1,
+                format!("address {:x} is past the end of memory", addr),
+            ));
+        }
+        match statement {
+            Statement::Instr { mnemonic, operands } => {
+                let resolved = operands
+                    .iter()
+                    .map(|token| asm.resolve(line, token))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let texts: Vec<&str> = resolved.iter().map(String::as_str).collect();
+                // point at the operand at fault, or the mnemonic
+                let instr = parse_instruction(mnemonic.text, &texts).map_err(|(i, err)| {
+                    let column = i.map_or(mnemonic.column, |i| operands[i].column);
+                    error(line, column, err)
+                })?;
+                out.extend_from_slice(&instr.to_bytes());
+            }
+            Statement::Bytes(bytes) => {
+                for token in bytes {
+                    let val = asm.number(line, token)?;
+                    if val > 0xFF {
+                        return Err(error(
+                            line,
+                            token.column,
+                            format!("{} does not fit in a byte", token.text),
+                        ));
+                    }
+                    out.push(val as u8);
+                }
+            }
+            Statement::Sprite(rows) => {
+                for token in rows {
+                    out.push(Assembler::sprite_row(line, token)?);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_assemble() {
+    let source = "
+        :const SPEED 3
+        :alias x v1
+    start:  LD x, SPEED     ; move right
+            LD I, ball
+    loop:   DRW x, V2, 4
+            ADD x, 1
+            JP loop
+    ball:   SPRITE \".XX.\", \"XXXX\"
+            DB 0xff, 0b101, 7
+    ";
+    assert_eq!(
+        assemble(source),
+        Ok(vec![
+            0x61, 0x03, 0xA2, 0x0A, 0xD1, 0x24, 0x71, 0x01, 0x12, 0x04, 0x60, 0xF0, 0xFF, 0x05,
+            0x07
+        ])
+    );
+
+    let err = assemble("CLS\n    JP nowhere").unwrap_err();
+    assert_eq!((err.line, err.column), (2, 8));
+    let err = assemble("LD V1, 0x100").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 8));
+    assert_eq!(err.message, "0x100 does not fit in 8 bits");
+    let err = assemble("  DRW V1, VG, 4").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 11));
+    let err = assemble("LD V1").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 1));
+}
+
+#[test]
+fn test_disassembly_round_trip() {
+    use crate::disasm::{disassemble, Syntax};
+    let rom = [
+        0x22, 0x0B, // CALL into odd addresses
+        0xA2, 0x0F, // LD I, into the middle of the RET
+        0x60, 0x05, 0x30, 0x05, 0x12, 0x02, 0xFF, 0xD0, 0x15, 0x00, 0xEE, 0xF0, 0x90, 0xF0,
+    ];
+    let source = disassemble(&rom, Syntax::Classic);
+    assert_eq!(assemble(&source), Ok(rom.to_vec()));
+}
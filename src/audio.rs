@@ -0,0 +1,277 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::scheduler::FRAME_RATE;
+
+// XO-CHIP pitch register value that plays the pattern at 4000 Hz
+pub const DEFAULT_PITCH: u8 = 64;
+pub const PATTERN_SIZE: usize = 16;
+
+// the buzzer during one 60 Hz tick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SoundFrame {
+    // the sound timer was non-zero
+    pub on: bool,
+    // XO-CHIP 1-bit sample loaded with F002, plays instead of the tone
+    pub pattern: Option<[u8; PATTERN_SIZE]>,
+    // XO-CHIP FX3A
+    pub pitch: u8,
+}
+
+impl SoundFrame {
+    // bits per second the pattern plays at
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+// anything that can play the buzzer (an audio device, a file...), gets
+// every timer tick so even a 1 tick beep is heard
+pub trait AudioSink {
+    fn tick(&mut self, frame: &SoundFrame);
+}
+
+// lets the caller keep a handle on a sink the machine owns
+impl<T: AudioSink> AudioSink for Rc<RefCell<T>> {
+    fn tick(&mut self, frame: &SoundFrame) {
+        self.borrow_mut().tick(frame);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    // one period, phase in [0, 1), result in [-1, 1]
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Triangle => "triangle",
+            Waveform::Sawtooth => "sawtooth",
+            Waveform::Sine => "sine",
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" | "saw" => Ok(Waveform::Sawtooth),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(format!(
+                "unknown waveform {} (expected square, triangle, sawtooth or sine)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSettings {
+    pub waveform: Waveform,
+    pub frequency: f32, // Hz
+    pub volume: f32,    // 0.0 to 1.0
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+// turns sound frames into mono f32 samples, keeping the phase across frames
+// so a held tone doesn't click at every tick
+pub struct Synth {
+    settings: AudioSettings,
+    sample_rate: u32,
+    phase: f32,
+    // fraction of a sample left over from the previous frame
+    carry: f32,
+}
+
+impl Synth {
+    pub fn new(settings: AudioSettings, sample_rate: u32) -> Synth {
+        Synth {
+            settings,
+            sample_rate,
+            phase: 0.0,
+            carry: 0.0,
+        }
+    }
+
+    pub fn get_settings(&self) -> AudioSettings {
+        self.settings
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // appends 1/60 s of samples (silence when the buzzer is off)
+    pub fn render(&mut self, frame: &SoundFrame, out: &mut Vec<f32>) {
+        let samples = self.sample_rate as f32 / FRAME_RATE as f32 + self.carry;
+        let count = samples as usize;
+        self.carry = samples - count as f32;
+        if !frame.on {
+            self.phase = 0.0;
+            out.resize(out.len() + count, 0.0);
+            return;
+        }
+        let volume = self.settings.volume;
+        match &frame.pattern {
+            // phase walks over the 128 pattern bits
+            Some(pattern) => {
+                let bits = (PATTERN_SIZE * 8) as f32;
+                let step = frame.pattern_rate() / self.sample_rate as f32 / bits;
+                for _ in 0..count {
+                    let bit = (self.phase * bits) as usize;
+                    let on = pattern[bit / 8] >> (7 - bit % 8) & 1;
+                    out.push(if on == 1 { volume } else { -volume });
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+            None => {
+                let step = self.settings.frequency / self.sample_rate as f32;
+                for _ in 0..count {
+                    out.push(self.settings.waveform.sample(self.phase) * volume);
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+        }
+    }
+}
+
+// collects everything a ROM plays, for writing it out as a WAV file
+pub struct WavRecorder {
+    synth: Synth,
+    samples: Vec<f32>,
+}
+
+impl WavRecorder {
+    pub fn new(settings: AudioSettings, sample_rate: u32) -> WavRecorder {
+        WavRecorder {
+            synth: Synth::new(settings, sample_rate),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    // 16-bit mono PCM
+    pub fn write_wav<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let sample_rate = self.synth.get_sample_rate();
+        let data_len = self.samples.len() as u32 * 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + data_len).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // channels
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+        out.write_all(&2u16.to_le_bytes())?; // bytes per frame
+        out.write_all(&16u16.to_le_bytes())?; // bits per sample
+        out.write_all(b"data")?;
+        out.write_all(&data_len.to_le_bytes())?;
+        for sample in &self.samples {
+            let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            out.write_all(&pcm.to_le_bytes())?;
+        }
+        out.flush()
+    }
+}
+
+impl AudioSink for WavRecorder {
+    fn tick(&mut self, frame: &SoundFrame) {
+        self.synth.render(frame, &mut self.samples);
+    }
+}
+
+#[test]
+fn test_synth_render() {
+    let settings = AudioSettings {
+        waveform: Waveform::Square,
+        frequency: 1000.0,
+        volume: 0.5,
+    };
+    let mut synth = Synth::new(settings, 6000);
+    let mut out = Vec::new();
+    let mut frame = SoundFrame {
+        on: true,
+        pattern: None,
+        pitch: DEFAULT_PITCH,
+    };
+    synth.render(&frame, &mut out);
+    // 100 samples per frame, 6 per period
+    assert_eq!(out.len(), 100);
+    assert_eq!(&out[..6], &[0.5, 0.5, 0.5, -0.5, -0.5, -0.5]);
+    frame.on = false;
+    synth.render(&frame, &mut out);
+    assert!(out[100..].iter().all(|s| *s == 0.0));
+    assert_eq!("saw".parse(), Ok(Waveform::Sawtooth));
+}
+
+#[test]
+fn test_pattern_playback() {
+    let mut frame = SoundFrame {
+        on: true,
+        pattern: Some([0xF0; PATTERN_SIZE]),
+        pitch: DEFAULT_PITCH,
+    };
+    assert_eq!(frame.pattern_rate(), 4000.0);
+    frame.pitch = 112;
+    assert_eq!(frame.pattern_rate(), 8000.0);
+
+    // 8000 bits per second at 8000 Hz is one bit per sample
+    let mut recorder = WavRecorder::new(AudioSettings::default(), 8000);
+    recorder.tick(&frame);
+    let samples = recorder.samples();
+    assert!(samples[..4].iter().all(|s| *s > 0.0));
+    assert!(samples[4..8].iter().all(|s| *s < 0.0));
+
+    let mut wav = Vec::new();
+    recorder.write_wav(&mut wav).unwrap();
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(wav.len(), 44 + samples.len() * 2);
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::display::Framebuffer;
+use crate::scheduler::FRAME_RATE;
+use crate::video::{Palette, VideoSink};
+use crate::{HIRES_COLS, HIRES_ROWS};
+
+// output pixels per hires pixel, a lores pixel is twice that
+pub const DEFAULT_CAPTURE_SCALE: u32 = 4;
+
+// GIF LZW codes are at most 12 bits
+const MAX_LZW_CODES: u16 = 4096;
+
+// A video of the screen, fed one frame per 60 Hz tick through `present`.
+// Recordings are always hires sized (times the scale), lores frames are
+// drawn with 2x2 pixels so a resolution switch doesn't change the size.
+pub trait Capture: VideoSink {
+    // writes whatever is still buffered, reports the first write error
+    fn close(self: Box<Self>) -> io::Result<()>;
+}
+
+// GIF for anything but a .y4m path, `-` is Y4M on stdout
+pub fn open(path: &str, palette: &Palette, scale: u32) -> io::Result<Box<dyn Capture>> {
+    if path == "-" {
+        let out: Box<dyn Write> = Box::new(BufWriter::new(io::stdout()));
+        return Ok(Box::new(Y4mRecorder::new(out, palette, scale)));
+    }
+    let out: Box<dyn Write> = Box::new(BufWriter::new(File::create(path)?));
+    if path.to_ascii_lowercase().ends_with(".y4m") {
+        Ok(Box::new(Y4mRecorder::new(out, palette, scale)))
+    } else {
+        Ok(Box::new(GifRecorder::new(out, palette, scale)))
+    }
+}
+
+// plane masks of the screen scaled to width x height, row major
+fn rasterize(framebuffer: &Framebuffer, width: usize, height: usize) -> Vec<u8> {
+    let rows = framebuffer.buffer();
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &rows[y * framebuffer.num_rows / height];
+        out.extend((0..width).map(|x| row[x * framebuffer.num_cols / width] & 0b11));
+    }
+    out
+}
+
+fn canvas_size(scale: u32) -> (usize, usize) {
+    let scale = scale.max(1) as usize;
+    (HIRES_COLS as usize * scale, HIRES_ROWS as usize * scale)
+}
+
+// LSB first bit packing for GIF's LZW codes
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u32) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+// GIF flavored LZW: starts with a clear code, code width grows as the
+// dictionary fills and everything is cleared once it's full at 12 bits
+fn lzw_encode(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = BitWriter {
+        bytes: Vec::new(),
+        acc: 0,
+        bits: 0,
+    };
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut width = min_code_size + 1;
+    let mut next = end + 1;
+    out.write(clear, width);
+    let Some((&first, rest)) = indices.split_first() else {
+        out.write(end, width);
+        return out.finish();
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = dict.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        out.write(prefix, width);
+        if next == MAX_LZW_CODES {
+            out.write(clear, width);
+            dict.clear();
+            width = min_code_size + 1;
+            next = end + 1;
+        } else {
+            // the decoder adds this entry one code later, and widens then
+            if next == 1 << width {
+                width += 1;
+            }
+            dict.insert((prefix, index), next);
+            next += 1;
+        }
+        prefix = index as u16;
+    }
+    out.write(prefix, width);
+    if next == 1 << width && next < MAX_LZW_CODES {
+        width += 1;
+    }
+    out.write(end, width);
+    out.finish()
+}
+
+// Animated GIF with the palette's four colors. Frames that don't change are
+// merged into the one before, delays (in 1/100 s) are rounded so they add
+// up to real time at 60 Hz.
+pub struct GifRecorder<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    // the frame waiting for its delay to be known
+    pending: Option<Vec<u8>>,
+    // 60 Hz ticks seen, and 1/100 s of delay written so far
+    ticks: u64,
+    centis: u64,
+    started: bool,
+    palette: Palette,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> GifRecorder<W> {
+    pub fn new(out: W, palette: &Palette, scale: u32) -> GifRecorder<W> {
+        let (width, height) = canvas_size(scale);
+        GifRecorder {
+            out,
+            width,
+            height,
+            pending: None,
+            ticks: 0,
+            centis: 0,
+            started: false,
+            palette: *palette,
+            error: None,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.out.write_all(b"GIF89a")?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        // global color table of 4 entries, background 0, square pixels
+        self.out.write_all(&[0x91, 0, 0])?;
+        for color in &self.palette.colors {
+            self.out.write_all(&color[..3])?;
+        }
+        // loop forever
+        self.out
+            .write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")
+    }
+
+    fn write_frame(&mut self, indices: &[u8], delay: u16) -> io::Result<()> {
+        // graphic control extension with the delay
+        self.out.write_all(&[0x21, 0xF9, 4, 0])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+        // full frame image descriptor, no local color table
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0, 2])?;
+        for block in lzw_encode(indices, 2).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+
+    fn flush_pending(&mut self) -> io::Result<()> {
+        if let Some(indices) = self.pending.take() {
+            // round to the nearest 1/100 s of where this frame ends
+            let end = (self.ticks * 100 + FRAME_RATE as u64 / 2) / FRAME_RATE as u64;
+            let delay = (end - self.centis).min(u16::MAX as u64);
+            self.centis += delay;
+            self.write_frame(&indices, delay as u16)?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, framebuffer: &Framebuffer) -> io::Result<()> {
+        if !self.started {
+            self.write_header()?;
+            self.started = true;
+        }
+        let indices = rasterize(framebuffer, self.width, self.height);
+        if self.pending.as_ref() != Some(&indices) {
+            self.flush_pending()?;
+            self.pending = Some(indices);
+        }
+        self.ticks += 1;
+        Ok(())
+    }
+
+    // writes the last frame and the trailer, hands back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if self.started {
+            self.flush_pending()?;
+            self.out.write_all(&[0x3B])?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> VideoSink for GifRecorder<W> {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        // after the first error there's nothing sensible left to write
+        if self.error.is_none() {
+            if let Err(err) = self.record(framebuffer) {
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+impl<W: Write> Capture for GifRecorder<W> {
+    fn close(self: Box<Self>) -> io::Result<()> {
+        self.finish().map(|_| ())
+    }
+}
+
+// BT.601 limited range, what Y4M readers expect
+fn to_yuv(color: [u8; 4]) -> [u8; 3] {
+    let [r, g, b] = [color[0] as f32, color[1] as f32, color[2] as f32];
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let u = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let v = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    [y.round() as u8, u.round() as u8, v.round() as u8]
+}
+
+// Raw YUV4MPEG2 stream (4:4:4, 60 fps) for handing to an encoder, e.g.
+// `ffmpeg -i capture.y4m capture.mp4`. Every tick is a frame.
+pub struct Y4mRecorder<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    // per plane mask
+    yuv: [[u8; 3]; 4],
+    started: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Y4mRecorder<W> {
+    pub fn new(out: W, palette: &Palette, scale: u32) -> Y4mRecorder<W> {
+        let (width, height) = canvas_size(scale);
+        Y4mRecorder {
+            out,
+            width,
+            height,
+            yuv: palette.colors.map(to_yuv),
+            started: false,
+            error: None,
+        }
+    }
+
+    fn record(&mut self, framebuffer: &Framebuffer) -> io::Result<()> {
+        if !self.started {
+            writeln!(
+                self.out,
+                "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                self.width, self.height, FRAME_RATE
+            )?;
+            self.started = true;
+        }
+        let indices = rasterize(framebuffer, self.width, self.height);
+        self.out.write_all(b"FRAME\n")?;
+        for plane in 0..3 {
+            let bytes: Vec<u8> = indices
+                .iter()
+                .map(|index| self.yuv[*index as usize][plane])
+                .collect();
+            self.out.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> VideoSink for Y4mRecorder<W> {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        if self.error.is_none() {
+            if let Err(err) = self.record(framebuffer) {
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+impl<W: Write> Capture for Y4mRecorder<W> {
+    fn close(self: Box<Self>) -> io::Result<()> {
+        self.finish().map(|_| ())
+    }
+}
+
+#[test]
+fn test_capture_formats() {
+    let mut fb = Framebuffer::new(32, 64);
+    let mut gif = GifRecorder::new(Vec::new(), &Palette::default(), 1);
+    let mut y4m = Y4mRecorder::new(Vec::new(), &Palette::default(), 1);
+    // 3 ticks of a blank screen, 1 with a pixel
+    for tick in 0..4 {
+        if tick == 3 {
+            fb.set_display(0, 0, 1);
+        }
+        gif.present(&fb);
+        y4m.present(&fb);
+    }
+    let gif = gif.finish().unwrap();
+    assert_eq!(&gif[..6], b"GIF89a");
+    assert_eq!(&gif[6..10], &[128, 0, 64, 0]);
+    assert_eq!(gif.last(), Some(&0x3B));
+    // two frames: 3 ticks = 5/100 s, then 1 tick rounds to 2/100 s
+    let delays: Vec<u16> = gif
+        .windows(4)
+        .filter(|w| w[..3] == [0x21, 0xF9, 4])
+        .map(|w| w.as_ptr() as usize - gif.as_ptr() as usize + 4)
+        .map(|at| u16::from_le_bytes([gif[at], gif[at + 1]]))
+        .collect();
+    assert_eq!(delays, vec![5, 2]);
+
+    let y4m = y4m.finish().unwrap();
+    let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+    assert!(y4m.starts_with(header));
+    assert_eq!(y4m.len(), header.len() + 4 * (6 + 128 * 64 * 3));
+    // lores pixel (0, 0) covers 2x2 hires pixels, white is Y 235
+    let last = y4m.len() - 128 * 64 * 3;
+    assert_eq!(&y4m[last..last + 3], &[235, 235, 16]);
+    assert_eq!(y4m[last + 128], 235);
+
+    // clear, 0, the new code for 0 0, end, all 3 bits wide
+    assert_eq!(lzw_encode(&[0, 0, 0], 2), vec![0x84, 0x0B]);
+}
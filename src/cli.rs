@@ -0,0 +1,475 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufWriter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chip8_rs::audio::AudioSettings;
+use chip8_rs::capture::DEFAULT_CAPTURE_SCALE;
+use chip8_rs::conformance::DEFAULT_TEST_FRAMES;
+use chip8_rs::disasm::Syntax;
+use chip8_rs::key_input::DEFAULT_KEY_TIMEOUT;
+use chip8_rs::movie::Movie;
+use chip8_rs::rewind::{DEFAULT_REWIND_BYTES, DEFAULT_REWIND_FRAMES};
+use chip8_rs::rng::RngKind;
+use chip8_rs::scheduler::DEFAULT_IPS;
+use chip8_rs::video::Palette;
+use chip8_rs::watch::Watchpoint;
+use chip8_rs::{rom, Chip8, FaultPolicy, Platform};
+
+pub const USAGE: &str = "Usage: chip8-rs [options] <rom.ch8>
+       chip8-rs disasm [--octo] <rom.ch8>
+       chip8-rs asm <source> [-o <out.ch8>]
+       chip8-rs wav [options] [--frames <n>] [-o <out.wav>] <rom.ch8>
+       chip8-rs play <movie> [options] <rom.ch8>
+       chip8-rs test-rom [options] [--frames <n>] [--input <script>|@<file>]
+                         [--golden <file> [--update] [--hash]] [--dump]
+                         [--screenshot <out.png|out.pbm> [--scale <n>]] <rom.ch8>
+       chip8-rs video [options] [--frames <n>] [--input <script>|@<file>] [--scale <n>]
+                      [-o <out.gif|out.y4m|->] <rom.ch8>
+Options:
+    --ips <n>                     instructions per second (default 700)
+    --on-fault halt|skip|nop      what to do when an instruction faults
+    --platform <name>             quirk preset: chip8 (default), vip, chip48, schip, xochip
+    --quirks <list>               override single quirks, e.g. `clip=off,shift=on`
+                                  (shift, memory, jump, vf-reset, clip, display-wait)
+    --seed <n>                    seed for CXNN random numbers (default: different every run)
+    --rng xorshift|vip            random generator, vip is a VIP-style 60 Hz counter
+    --palette <colors>            classic (default), amber, green, lcd, high-contrast,
+                                  colorblind, 2 or 4 hex colors (`#000000,#33ff66`) or
+                                  @<file> holding either; Tab cycles the presets
+    --trace <filter>              log filter, e.g. `debug` or `cpu=trace,input=info`
+                                  (categories: cpu, display, input, timers)
+    --trace-file <path>           write a JSON line per executed instruction
+    --watch <spec>                watch memory, e.g. `0x300-0x302:w:log` (r, w, x access,
+                                  stops the machine unless `:log`), can be repeated
+    --waveform <name>             buzzer waveform: square (default), triangle, sawtooth, sine
+    --tone <hz>                   buzzer frequency (default 440)
+    --volume <0-100>              buzzer volume in percent (default 25)
+    --rewind-frames <n>           frames kept for rewinding with backspace (default 600, 0 = off)
+    --rewind-mb <n>               memory the rewind buffer may use (default 16)
+    --record <movie>              record the keypad to a movie file
+    --play <movie>                play a movie back in the window
+    --capture <out.gif|out.y4m|-> record the window from the start (F10 toggles recording)
+    --debug                       step through the ROM in a command line debugger
+    --tui                         run in the terminal instead of a window (Esc quits)
+    --key-timeout <ms>            --tui: how long a key counts as held after a press
+                                  when the terminal doesn't report releases (default 500)";
+
+pub enum Command {
+    Run(Options),
+    Disasm {
+        rom_path: String,
+        syntax: Syntax,
+    },
+    Asm {
+        source_path: String,
+        out_path: String,
+    },
+    // run headless for a number of frames and write the sound to a file
+    Wav {
+        options: Options,
+        frames: u32,
+        out_path: String,
+    },
+    // replay a movie headless
+    Play {
+        movie_path: String,
+        options: Options,
+    },
+    // run headless and compare the screen with a golden file
+    TestRom {
+        options: Options,
+        test: TestRomOptions,
+    },
+    // run headless with scripted input and record the screen
+    Video {
+        options: Options,
+        frames: u32,
+        input: Option<String>,
+        scale: u32,
+        out_path: String,
+    },
+}
+
+pub struct TestRomOptions {
+    pub frames: u32,
+    // `conformance::parse_input_script` syntax, or @path to a file of it
+    pub input: Option<String>,
+    pub golden: Option<String>,
+    // write the golden file instead of checking it
+    pub update: bool,
+    // store only the framebuffer hash when updating
+    pub hash: bool,
+    // print the final screen
+    pub dump: bool,
+    // save the final screen as a PNG or PBM
+    pub screenshot: Option<String>,
+    pub scale: u32,
+}
+
+// 10 seconds
+pub const DEFAULT_WAV_FRAMES: u32 = 600;
+
+// some options only matter to the SDL frontend
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub struct Options {
+    pub rom_path: String,
+    pub ips: u32,
+    pub fault_policy: FaultPolicy,
+    pub platform: Platform,
+    pub quirks: Option<String>,
+    pub seed: Option<u64>,
+    pub rng: RngKind,
+    pub palette: Palette,
+    pub trace_filter: Option<String>,
+    pub trace_file: Option<String>,
+    pub watch: Vec<Watchpoint>,
+    pub audio: AudioSettings,
+    pub rewind_frames: usize,
+    pub rewind_bytes: usize,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub capture: Option<String>,
+    pub debug: bool,
+    pub tui: bool,
+    pub key_timeout: Duration,
+}
+
+fn flag_value<'a>(flag: &str, val: Option<&'a String>) -> Result<&'a String, String> {
+    val.ok_or(format!("Error: {} expects a value", flag))
+}
+
+fn parse_flag<T: FromStr>(flag: &str, val: Option<&String>) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    let val = flag_value(flag, val)?;
+    val.parse()
+        .map_err(|err| format!("Error: invalid value for {}: {} ({})", flag, val, err))
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    match args.get(1).map(String::as_str) {
+        Some("disasm") => parse_disasm_args(&args[2..]),
+        Some("asm") => parse_asm_args(&args[2..]),
+        Some("wav") => parse_wav_args(&args[2..]),
+        Some("play") => parse_play_args(&args[2..]),
+        Some("test-rom") => parse_test_rom_args(&args[2..]),
+        Some("video") => parse_video_args(&args[2..]),
+        _ => parse_run_args(&args[1..]).map(Command::Run),
+    }
+}
+
+fn parse_disasm_args(args: &[String]) -> Result<Command, String> {
+    let mut rom_path = None;
+    let mut syntax = Syntax::Classic;
+    for arg in args {
+        match arg.as_str() {
+            "--octo" => syntax = Syntax::Octo,
+            flag if flag.starts_with("--") => return Err(format!("Error: unknown flag {}", flag)),
+            path if rom_path.is_none() => rom_path = Some(path.to_string()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let rom_path = rom_path.ok_or("Error: disasm expects a ROM")?;
+    Ok(Command::Disasm { rom_path, syntax })
+}
+
+fn parse_asm_args(args: &[String]) -> Result<Command, String> {
+    let mut source_path = None;
+    let mut out_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = Some(flag_value(arg, args.next())?.clone()),
+            flag if flag.starts_with('-') => return Err(format!("Error: unknown flag {}", flag)),
+            path if source_path.is_none() => source_path = Some(path.to_string()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let source_path: String = source_path.ok_or("Error: asm expects a source file")?;
+    // foo.8o -> foo.ch8 unless told otherwise
+    let out_path = out_path.unwrap_or_else(|| {
+        std::path::Path::new(&source_path)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+    Ok(Command::Asm {
+        source_path,
+        out_path,
+    })
+}
+
+fn parse_wav_args(args: &[String]) -> Result<Command, String> {
+    let mut frames = DEFAULT_WAV_FRAMES;
+    let mut out_path = None;
+    // everything else is a run option
+    let mut run_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = parse_flag(arg, args.next())?,
+            "-o" => out_path = Some(flag_value(arg, args.next())?.clone()),
+            _ => run_args.push(arg.clone()),
+        }
+    }
+    let options = parse_run_args(&run_args)?;
+    // foo.ch8 -> foo.wav unless told otherwise
+    let out_path = out_path.unwrap_or_else(|| {
+        std::path::Path::new(&options.rom_path)
+            .with_extension("wav")
+            .to_string_lossy()
+            .into_owned()
+    });
+    Ok(Command::Wav {
+        options,
+        frames,
+        out_path,
+    })
+}
+
+fn parse_play_args(args: &[String]) -> Result<Command, String> {
+    let (movie_path, rest) = args.split_first().ok_or("Error: play expects a movie")?;
+    Ok(Command::Play {
+        movie_path: movie_path.clone(),
+        options: parse_run_args(rest)?,
+    })
+}
+
+fn parse_test_rom_args(args: &[String]) -> Result<Command, String> {
+    let mut test = TestRomOptions {
+        frames: DEFAULT_TEST_FRAMES,
+        input: None,
+        golden: None,
+        update: false,
+        hash: false,
+        dump: false,
+        screenshot: None,
+        scale: 1,
+    };
+    // everything else is a run option
+    let mut run_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => test.frames = parse_flag(arg, args.next())?,
+            "--input" => test.input = Some(flag_value(arg, args.next())?.clone()),
+            "--golden" => test.golden = Some(flag_value(arg, args.next())?.clone()),
+            "--update" => test.update = true,
+            "--hash" => test.hash = true,
+            "--dump" => test.dump = true,
+            "--screenshot" => test.screenshot = Some(flag_value(arg, args.next())?.clone()),
+            "--scale" => test.scale = parse_flag(arg, args.next())?,
+            _ => run_args.push(arg.clone()),
+        }
+    }
+    if (test.update || test.hash) && test.golden.is_none() {
+        return Err("Error: --update and --hash need a --golden file".to_string());
+    }
+    Ok(Command::TestRom {
+        options: parse_run_args(&run_args)?,
+        test,
+    })
+}
+
+fn parse_video_args(args: &[String]) -> Result<Command, String> {
+    let mut frames = DEFAULT_WAV_FRAMES;
+    let mut input = None;
+    let mut scale = DEFAULT_CAPTURE_SCALE;
+    let mut out_path = None;
+    // everything else is a run option
+    let mut run_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = parse_flag(arg, args.next())?,
+            "--input" => input = Some(flag_value(arg, args.next())?.clone()),
+            "--scale" => scale = parse_flag(arg, args.next())?,
+            "-o" => out_path = Some(flag_value(arg, args.next())?.clone()),
+            _ => run_args.push(arg.clone()),
+        }
+    }
+    let options = parse_run_args(&run_args)?;
+    // foo.ch8 -> foo.gif unless told otherwise
+    let out_path = out_path.unwrap_or_else(|| {
+        std::path::Path::new(&options.rom_path)
+            .with_extension("gif")
+            .to_string_lossy()
+            .into_owned()
+    });
+    Ok(Command::Video {
+        options,
+        frames,
+        input,
+        scale,
+        out_path,
+    })
+}
+
+// a palette spec, or @path to a file holding one
+fn parse_palette(spec: &str) -> Result<Palette, String> {
+    let spec = match spec.strip_prefix('@') {
+        Some(path) => {
+            std::fs::read_to_string(path).map_err(|err| format!("Error: {}: {}", path, err))?
+        }
+        None => spec.to_string(),
+    };
+    spec.parse()
+        .map_err(|err| format!("Error: invalid value for --palette: {}", err))
+}
+
+fn parse_run_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut ips = DEFAULT_IPS;
+    let mut fault_policy = FaultPolicy::default();
+    let mut platform = Platform::default();
+    let mut quirks = None;
+    let mut seed = None;
+    let mut rng = RngKind::default();
+    let mut palette = Palette::default();
+    let mut trace_filter = None;
+    let mut trace_file = None;
+    let mut watch = Vec::new();
+    let mut audio = AudioSettings::default();
+    let mut rewind_frames = DEFAULT_REWIND_FRAMES;
+    let mut rewind_bytes = DEFAULT_REWIND_BYTES;
+    let mut record = None;
+    let mut play = None;
+    let mut capture = None;
+    let mut debug = false;
+    let mut tui = false;
+    let mut key_timeout = DEFAULT_KEY_TIMEOUT;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ips" => ips = parse_flag(arg, args.next())?,
+            "--on-fault" => fault_policy = parse_flag(arg, args.next())?,
+            "--platform" => platform = parse_flag(arg, args.next())?,
+            "--quirks" => quirks = Some(flag_value(arg, args.next())?.clone()),
+            "--seed" => seed = Some(parse_flag(arg, args.next())?),
+            "--rng" => rng = parse_flag(arg, args.next())?,
+            "--palette" => palette = parse_palette(flag_value(arg, args.next())?)?,
+            "--trace" => trace_filter = Some(flag_value(arg, args.next())?.clone()),
+            "--trace-file" => trace_file = Some(flag_value(arg, args.next())?.clone()),
+            "--watch" => watch.push(parse_flag(arg, args.next())?),
+            "--waveform" => audio.waveform = parse_flag(arg, args.next())?,
+            "--tone" => {
+                audio.frequency = parse_flag(arg, args.next())?;
+                if audio.frequency.is_nan() || audio.frequency <= 0.0 {
+                    return Err(format!("Error: {} must be positive", arg));
+                }
+            }
+            "--volume" => {
+                let percent: u8 = parse_flag(arg, args.next())?;
+                if percent > 100 {
+                    return Err(format!("Error: {} must be between 0 and 100", arg));
+                }
+                audio.volume = percent as f32 / 100.0;
+            }
+            "--rewind-frames" => rewind_frames = parse_flag(arg, args.next())?,
+            "--rewind-mb" => {
+                let mb: usize = parse_flag(arg, args.next())?;
+                rewind_bytes = mb
+                    .checked_mul(1024 * 1024)
+                    .ok_or(format!("Error: {} is too large: {}", arg, mb))?;
+            }
+            "--record" => record = Some(flag_value(arg, args.next())?.clone()),
+            "--play" => play = Some(flag_value(arg, args.next())?.clone()),
+            "--capture" => capture = Some(flag_value(arg, args.next())?.clone()),
+            "--debug" => debug = true,
+            "--tui" => tui = true,
+            "--key-timeout" => key_timeout = Duration::from_millis(parse_flag(arg, args.next())?),
+            flag if flag.starts_with("--") => return Err(format!("Error: unknown flag {}", flag)),
+            path if rom_path.is_none() => rom_path = Some(path.to_string()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    if record.is_some() && play.is_some() {
+        return Err("Error: --record and --play can't be used together".to_string());
+    }
+
+    Ok(Options {
+        rom_path: rom_path.unwrap_or("./chip8-roms/programs/IBM Logo.ch8".to_string()),
+        ips,
+        fault_policy,
+        platform,
+        quirks,
+        seed,
+        rng,
+        palette,
+        trace_filter,
+        trace_file,
+        watch,
+        audio,
+        rewind_frames,
+        rewind_bytes,
+        record,
+        play,
+        capture,
+        debug,
+        tui,
+        key_timeout,
+    })
+}
+
+impl Options {
+    // a machine configured from the command line with the ROM loaded
+    pub fn build_chip8(&self) -> Result<Chip8, String> {
+        let mut chip8 = Chip8::with_platform(self.platform);
+        if let Some(spec) = &self.quirks {
+            let mut quirks = chip8.get_quirks();
+            quirks
+                .apply(spec)
+                .map_err(|err| format!("Error: {}", err))?;
+            chip8.set_quirks(quirks);
+        }
+        chip8.set_rng(self.rng.build(0).unwrap());
+        if let Some(seed) = self.seed {
+            chip8.seed_rng(seed);
+        }
+        self.configure_host(&mut chip8)?;
+
+        rom::read_rom(self.rom_path.clone())
+            .and_then(|instrs| chip8.load_to_ram(&instrs))
+            .map_err(|err| format!("Error: {}", err))?;
+        Ok(chip8)
+    }
+
+    // a machine set up like the one the movie was recorded on, platform,
+    // quirks and RNG seed come from the movie rather than the command line
+    pub fn build_movie_chip8(&self, movie: &Movie) -> Result<Chip8, String> {
+        let rom = rom::read_rom(self.rom_path.clone()).map_err(|err| format!("Error: {}", err))?;
+        let mut chip8 = movie
+            .build_chip8(&rom)
+            .map_err(|err| format!("Error: {}", err))?;
+        self.configure_host(&mut chip8)?;
+        Ok(chip8)
+    }
+
+    // the settings that don't change how a ROM runs
+    fn configure_host(&self, chip8: &mut Chip8) -> Result<(), String> {
+        chip8.set_fault_policy(self.fault_policy);
+        if let Some(filter) = &self.trace_filter {
+            chip8
+                .tracer
+                .set_filter(filter)
+                .map_err(|err| format!("Error: {}", err))?;
+        }
+        if let Some(path) = &self.trace_file {
+            let file = File::create(path).map_err(|err| format!("Error: {}: {}", path, err))?;
+            chip8.tracer.set_instr_trace(Box::new(BufWriter::new(file)));
+        }
+        for watchpoint in &self.watch {
+            chip8.watchpoints.add(*watchpoint);
+        }
+        Ok(())
+    }
+}
+
+pub fn read_movie(path: &str) -> Result<Movie, String> {
+    let data = std::fs::read(path).map_err(|err| format!("Error: {}: {}", path, err))?;
+    Movie::read(&data).map_err(|err| format!("Error: {}: {}", path, err))
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+
+use crate::display::{grid_symbol, Framebuffer};
+
+// run test ROMs for 5 seconds unless told otherwise
+pub const DEFAULT_TEST_FRAMES: u32 = 300;
+
+// Scripted keypad input for headless runs: entries `frame:key[:frames]`
+// separated by commas, spaces or newlines, `#` starts a comment. The key
+// (hex) is held from the start of that frame for the given number of
+// frames (default 1), e.g. `30:5, 90:a:10`.
+pub fn parse_input_script(script: &str, total_frames: u32) -> Result<Vec<u16>, String> {
+    let mut frames = vec![0u16; total_frames as usize];
+    let entries = script
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split([',', ' ', '\t']))
+        .filter(|entry| !entry.is_empty());
+    for entry in entries {
+        let parts: Vec<&str> = entry.split(':').collect();
+        let (frame, key, hold) = match parts.as_slice() {
+            [frame, key] => (*frame, *key, "1"),
+            [frame, key, hold] => (*frame, *key, *hold),
+            _ => return Err(format!("expected frame:key[:frames], got {}", entry)),
+        };
+        let frame: u32 = frame
+            .parse()
+            .map_err(|_| format!("bad frame in {}", entry))?;
+        let key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|key| *key < 16)
+            .ok_or(format!("bad key in {} (0-f)", entry))?;
+        let hold: u32 = hold
+            .parse()
+            .map_err(|_| format!("bad frame count in {}", entry))?;
+        for keys in frames.iter_mut().skip(frame as usize).take(hold as usize) {
+            *keys |= 1 << key;
+        }
+    }
+    Ok(frames)
+}
+
+// What a test ROM's screen should look like at the end of a run: either
+// the `Framebuffer::hash` (16 hex digits) or the `render_grid` text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Golden {
+    Hash(u64),
+    Grid(String),
+}
+
+fn symbol_cell(symbol: char) -> u8 {
+    match symbol {
+        ' ' | '.' => 0,
+        '+' => 2,
+        '#' => 3,
+        _ => 1,
+    }
+}
+
+impl Golden {
+    pub fn parse(text: &str) -> Golden {
+        let trimmed = text.trim();
+        match u64::from_str_radix(trimmed, 16) {
+            Ok(hash) if trimmed.len() == 16 => Golden::Hash(hash),
+            _ => Golden::Grid(text.to_string()),
+        }
+    }
+
+    pub fn from_framebuffer(framebuffer: &Framebuffer, as_hash: bool) -> Golden {
+        match as_hash {
+            true => Golden::Hash(framebuffer.hash()),
+            false => Golden::Grid(framebuffer.render_grid()),
+        }
+    }
+
+    // Ok if the screen matches, otherwise what differs as text. Grids are
+    // compared ignoring trailing spaces and blank lines, which editors like
+    // to strip.
+    pub fn check(&self, framebuffer: &Framebuffer) -> Result<(), String> {
+        match self {
+            Golden::Hash(hash) if *hash == framebuffer.hash() => Ok(()),
+            Golden::Hash(hash) => Err(format!(
+                "expected framebuffer {:016x}, got {:016x}\n{}",
+                hash,
+                framebuffer.hash(),
+                framebuffer.render_grid()
+            )),
+            Golden::Grid(grid) => grid_diff(grid, framebuffer),
+        }
+    }
+}
+
+impl fmt::Display for Golden {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Golden::Hash(hash) => writeln!(f, "{:016x}", hash),
+            Golden::Grid(grid) => write!(f, "{}", grid),
+        }
+    }
+}
+
+// The screen with every cell that doesn't match the golden grid marked:
+// '-' lit in the golden grid only, '*' lit on the screen only, '!' lit in
+// both but in different XO-CHIP planes.
+fn grid_diff(grid: &str, framebuffer: &Framebuffer) -> Result<(), String> {
+    let expected: Vec<Vec<u8>> = grid
+        .lines()
+        .map(|line| line.trim_end().chars().map(symbol_cell).collect())
+        .collect();
+    let too_big = expected.len() > framebuffer.num_rows
+        && expected[framebuffer.num_rows..]
+            .iter()
+            .any(|row| !row.is_empty())
+        || expected.iter().any(|row| row.len() > framebuffer.num_cols);
+    let mut mismatches = 0;
+    let mut out = String::new();
+    for (r, row) in framebuffer.buffer().iter().enumerate() {
+        out.push('|');
+        for (c, cell) in row.iter().enumerate() {
+            let want = expected
+                .get(r)
+                .and_then(|row| row.get(c))
+                .copied()
+                .unwrap_or(0);
+            let symbol = match (want, *cell) {
+                (want, got) if want == got => grid_symbol(got),
+                (_, 0) => '-',
+                (0, _) => '*',
+                _ => '!',
+            };
+            mismatches += (want != *cell) as usize;
+            out.push(symbol);
+        }
+        out.push_str("|\n");
+    }
+    if mismatches == 0 && !too_big {
+        return Ok(());
+    }
+    let mut summary = format!("{} cells differ", mismatches);
+    if too_big {
+        summary += &format!(
+            ", golden grid is bigger than the {}x{} screen",
+            framebuffer.num_cols, framebuffer.num_rows
+        );
+    }
+    Err(format!(
+        "{} ('-' golden only, '*' screen only, '!' other plane)\n{}",
+        summary, out
+    ))
+}
+
+#[test]
+fn test_golden_check() {
+    let frames = parse_input_script("1:5 # start\n2:a:2, 2:0", 5).unwrap();
+    assert_eq!(frames, vec![0, 1 << 5, 1 << 10 | 1, 1 << 10, 0]);
+    assert!(parse_input_script("1:g", 5).is_err());
+
+    let mut framebuffer = Framebuffer::new(2, 4);
+    framebuffer.set_display(0, 1, 1);
+    framebuffer.set_display(1, 3, 1);
+    let grid = Golden::from_framebuffer(&framebuffer, false);
+    assert_eq!(grid.to_string(), " X  \n   X\n");
+    // trailing whitespace doesn't matter
+    assert_eq!(Golden::parse(" X\n   X").check(&framebuffer), Ok(()));
+    let hash = Golden::parse(&Golden::from_framebuffer(&framebuffer, true).to_string());
+    assert_eq!(hash.check(&framebuffer), Ok(()));
+
+    let diff = Golden::parse("XX\n").check(&framebuffer).unwrap_err();
+    assert_eq!(
+        diff,
+        "2 cells differ ('-' golden only, '*' screen only, '!' other plane)\n|-X  |\n|   *|\n"
+    );
+    let too_big = Golden::parse(" X    X").check(&framebuffer).unwrap_err();
+    assert!(
+        too_big.contains("bigger than the 4x2 screen"),
+        "{}",
+        too_big
+    );
+}
@@ -1,10 +1,14 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::{thread::sleep, time::Duration};
-
-use crate::display::{self};
+use crate::audio::{AudioSink, SoundFrame, DEFAULT_PITCH, PATTERN_SIZE};
+use crate::display::Framebuffer;
 use crate::emu_timer::EmuTimer;
-use crate::{instr, key_input};
+use crate::error::{Chip8Error, FaultPolicy};
+use crate::key_input::KeyInput;
+use crate::quirks::{Platform, Quirks};
+use crate::rng::{RandomSource, RngKind};
+use crate::savestate::{StateError, StateReader, StateWriter};
+use crate::trace::{trace, InstrRecord, Tracer};
+use crate::watch::{AccessKind, MemAccess, Watchpoints};
+use crate::{instr, HIRES_COLS, HIRES_ROWS, NUM_COLS, NUM_ROWS};
 
 const FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -24,10 +28,37 @@ const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+pub const FONT_ADDR: u16 = 0x050;
+
+// 8x10 digits for FX30 (SUPER-CHIP has 0-9, XO-CHIP added A-F)
+const BIG_FONT_SET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+pub const BIG_FONT_ADDR: u16 = 0x0A0;
+
+// SUPER-CHIP persistent "RPL user flags" for FX75/FX85
+pub const NUM_RPL_FLAGS: usize = 16;
+
+// calls nested deeper than this overflow the stack
+pub const STACK_SIZE: usize = 16;
+// programs are loaded here, everything below is reserved for the interpreter
+pub const PROGRAM_START: usize = 0x200;
 
-// 700 instructions per second
-// each instruction should then take 1000/700
-const CLOCK_CYCLE: Duration = Duration::from_millis(15);
 // 16 8-bit data registers named V0 to VF
 struct CPUState {
     v_regs: [u8; 16], // data regs
@@ -45,89 +76,340 @@ impl CPUState {
     }
 }
 
-pub struct Chip8<'a> {
+pub struct Chip8 {
     state: CPUState,
-    ram: [u8; 4096], // 12 bit register for address, always mask by &= 0xFFF
+    ram: Vec<u8>, // 4 KiB, or 64 KiB on XO-CHIP
     stack: Vec<u16>,
-    pub display: &'a mut display::Display, // 32 rows, 64 columns, each can be 0 or 1 (on or off) - 0 is black, 1 is white
-    key_input: Rc<RefCell<key_input::KeyInput>>,
+    pub display: Framebuffer, // 64x32 or 128x64 (hires), 0/1 per pixel or XO-CHIP plane masks
+    pub key_input: KeyInput,
+    waiting_for_key: bool,    // FX0A is blocking
+    waiting_for_vblank: bool, // drew with the display wait quirk on
     pub delay_timer: EmuTimer,
     pub sound_timer: EmuTimer,
+    audio: Option<Box<dyn AudioSink>>,
+    audio_pattern: Option<[u8; PATTERN_SIZE]>, // XO-CHIP F002
+    pitch: u8,                                 // XO-CHIP FX3A
+    pub tracer: Tracer,
+    pub watchpoints: Watchpoints,
+    watch_hit: Option<MemAccess>, // a break watchpoint stopped the machine
+    rng: Box<dyn RandomSource>,   // CXNN
+    rng_seed: u64,
+    fault_policy: FaultPolicy,
+    halted: Option<Chip8Error>,
+    exited: bool, // 00FD
+    hires: bool,
+    planes: u8, // XO-CHIP bitplanes drawn to, bit 0 = plane 1, bit 1 = plane 2
+    rpl_flags: [u8; NUM_RPL_FLAGS],
+    platform: Platform,
+    quirks: Quirks,
+    curr_instr_addr: u16, // address of the instruction being executed
 }
 
 // load in Chip8 memory starting at address 0x200
-impl Chip8<'_> {
-    pub fn new(
-        display: &mut display::Display,
-        key_input: Rc<RefCell<key_input::KeyInput>>,
-    ) -> Chip8<'_> {
+impl Chip8 {
+    pub fn new() -> Chip8 {
+        Chip8::with_platform(Platform::default())
+    }
+
+    // a machine with the quirks of the given platform
+    pub fn with_platform(platform: Platform) -> Chip8 {
+        // a different run every time unless seeded with `seed_rng`
+        let rng_seed = rand::random();
         // initialize the font system
-        let mut ram = [0; 4096];
-        ram[0x050..0x0A0].copy_from_slice(&FONT_SET);
+        let mut ram = vec![0; platform.memory_size()];
+        ram[FONT_ADDR as usize..FONT_ADDR as usize + FONT_SET.len()].copy_from_slice(&FONT_SET);
+        ram[BIG_FONT_ADDR as usize..BIG_FONT_ADDR as usize + BIG_FONT_SET.len()]
+            .copy_from_slice(&BIG_FONT_SET);
 
         Chip8 {
             state: CPUState::new(),
             ram,
             stack: Vec::new(),
-            display,
-            key_input,
+            display: Framebuffer::new(NUM_ROWS as usize, NUM_COLS as usize),
+            key_input: KeyInput::new(),
+            waiting_for_key: false,
+            waiting_for_vblank: false,
             delay_timer: EmuTimer::new(0),
             sound_timer: EmuTimer::new(0),
+            audio: None,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            tracer: Tracer::new(),
+            watchpoints: Watchpoints::new(),
+            watch_hit: None,
+            rng: RngKind::default().build(rng_seed).unwrap(),
+            rng_seed,
+            fault_policy: FaultPolicy::default(),
+            halted: None,
+            exited: false,
+            hires: false,
+            planes: 1,
+            rpl_flags: [0; NUM_RPL_FLAGS],
+            platform,
+            quirks: platform.quirks(),
+            curr_instr_addr: PROGRAM_START as u16,
         }
     }
 
-    pub fn cpu_loop(&mut self) {
-        // redraw display during every loop if needed
-        self.display.display_update();
+    pub fn cpu_loop(&mut self) -> Result<(), Chip8Error> {
+        if let Some(err) = &self.halted {
+            return Err(err.clone());
+        }
+        if self.exited || self.waiting_for_vblank || self.watch_hit.is_some() {
+            return Ok(());
+        }
+        // apply key presses/releases that came in since the last instruction
+        for event in self.key_input.pending_events() {
+            trace!(self.tracer, Input, Debug, "{:?}", event);
+        }
+        self.key_input.process_events();
         // fetch
-        let curr_instr = {
-            let curr_instr_1 = self.ram[self.state.pc as usize] as u16;
-            let curr_instr_2 = self.ram[(self.state.pc + 1) as usize] as u16;
-            (curr_instr_1 << 8) | curr_instr_2
+        self.curr_instr_addr = self.state.pc;
+        let curr_instr = match self.fetch_word(self.state.pc) {
+            Ok(opcode) => opcode,
+            // there's no next instruction to skip to, so whatever the policy
+            // the machine stops instead of faulting on every cycle
+            Err(err) => return self.halt(err),
         };
-        self.state.pc += 2;
+        if self.tracer.instr_trace_enabled() {
+            self.tracer.trace_instr(&InstrRecord {
+                pc: self.state.pc,
+                opcode: curr_instr,
+                v_regs: &self.state.v_regs,
+                i_reg: self.state.i_reg,
+                delay_timer: self.delay_timer.get_time_left(),
+                sound_timer: self.sound_timer.get_time_left(),
+            });
+        }
+        self.state.pc = self.state.pc.wrapping_add(2);
 
-        // handle timers
-        self.delay_timer.decr_time_left();
-        self.sound_timer.decr_time_left();
+        match instr::op(curr_instr, self) {
+            Ok(()) => Ok(()),
+            Err(err) => self.fault(err),
+        }
+    }
+
+    fn fault(&mut self, err: Chip8Error) -> Result<(), Chip8Error> {
+        trace!(self.tracer, Cpu, Error, "{} ({:?})", err, self.fault_policy);
+        match self.fault_policy {
+            FaultPolicy::Halt => self.halt(err),
+            FaultPolicy::Skip => Err(err),
+            FaultPolicy::NoOp => Ok(()),
+        }
+    }
+
+    fn halt(&mut self, err: Chip8Error) -> Result<(), Chip8Error> {
+        // leave pc on the faulting instruction
+        self.state.pc = self.curr_instr_addr;
+        self.halted = Some(err.clone());
+        Err(err)
+    }
+
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    pub fn get_fault_policy(&self) -> FaultPolicy {
+        self.fault_policy
+    }
+
+    pub fn get_platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // override the platform's quirks
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    // stall the CPU until the next 60 Hz tick
+    pub fn wait_for_vblank(&mut self) {
+        self.waiting_for_vblank = true;
+    }
+
+    // stopped by a fault or by the program exiting
+    pub fn is_halted(&self) -> bool {
+        self.halted.is_some() || self.exited
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
 
-        instr::op(curr_instr, self);
+    pub fn exit(&mut self) {
+        self.exited = true;
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // SUPER-CHIP 00FE/00FF
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        if hires {
+            self.display
+                .resize(HIRES_ROWS as usize, HIRES_COLS as usize);
+        } else {
+            self.display.resize(NUM_ROWS as usize, NUM_COLS as usize);
+        }
+    }
+
+    pub fn get_planes(&self) -> u8 {
+        self.planes
+    }
 
-        // simulate Chip8 speed
-        sleep(CLOCK_CYCLE);
+    // XO-CHIP FN01, only the low two bits are meaningful
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
     }
 
-    pub fn stack_push(&mut self, addr: u16) {
-        // TODO: account for limited stack space
-        self.stack.push(addr)
+    pub fn save_rpl_flags(&mut self, num_regs: u8) {
+        let n = num_regs as usize + 1;
+        self.rpl_flags[..n].copy_from_slice(&self.state.v_regs[..n]);
     }
 
-    pub fn stack_pop(&mut self) -> u16 {
-        match self.stack.pop() {
-            Some(addr) => addr,
-            None => panic!("Error: Popping from an empty stack"),
+    pub fn load_rpl_flags(&mut self, num_regs: u8) {
+        let n = num_regs as usize + 1;
+        self.state.v_regs[..n].copy_from_slice(&self.rpl_flags[..n]);
+    }
+
+    // gets a sound frame on every timer tick
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio = Some(sink);
+    }
+
+    // what the buzzer plays this tick
+    pub fn sound_frame(&self) -> SoundFrame {
+        SoundFrame {
+            on: self.sound_timer.get_time_left() > 0,
+            pattern: self.audio_pattern,
+            pitch: self.pitch,
         }
     }
 
-    pub fn load_to_ram(&mut self, instrs: &[u8]) {
-        // load all the instructions into memory starting at 0x200
-        let mut start = 0x200;
-        for instr in instrs {
-            self.ram[start] = *instr;
-            start += 1
+    // XO-CHIP F002: the 16 bytes at I become the sound
+    pub fn load_audio_pattern(&mut self) -> Result<(), Chip8Error> {
+        let mut pattern = [0; PATTERN_SIZE];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.get_mem_data(self.get_index_reg().wrapping_add(offset as u16))?;
+        }
+        self.audio_pattern = Some(pattern);
+        Ok(())
+    }
+
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    // restart the CXNN random numbers from a seed, for reproducible runs
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng.seed(seed);
+        self.rng_seed = seed;
+    }
+
+    pub fn get_rng_seed(&self) -> u64 {
+        self.rng_seed
+    }
+
+    // swap in another generator, started from the current seed
+    pub fn set_rng(&mut self, mut rng: Box<dyn RandomSource>) {
+        rng.seed(self.rng_seed);
+        self.rng = rng;
+    }
+
+    pub fn get_rng_kind(&self) -> RngKind {
+        self.rng.kind()
+    }
+
+    pub fn random_byte(&mut self) -> u8 {
+        self.rng.next_byte()
+    }
+
+    pub fn get_curr_instr_addr(&self) -> u16 {
+        self.curr_instr_addr
+    }
+
+    // 60 Hz tick, independent of how many instructions ran in between (see
+    // `scheduler::Scheduler` for the pacing)
+    pub fn tick_timers(&mut self) {
+        let frame = self.sound_frame();
+        if let Some(audio) = &mut self.audio {
+            audio.tick(&frame);
         }
+        self.delay_timer.decr_time_left();
+        self.sound_timer.decr_time_left();
+        self.rng.tick();
+        trace!(
+            self.tracer,
+            Timers,
+            Trace,
+            "tick: delay = {}, sound = {}",
+            self.delay_timer.get_time_left(),
+            self.sound_timer.get_time_left()
+        );
+        self.key_input.end_frame();
+        self.waiting_for_vblank = false;
     }
 
-    // for debugging
+    pub fn stack_push(&mut self, addr: u16) -> Result<(), Chip8Error> {
+        if self.stack.len() == STACK_SIZE {
+            return Err(Chip8Error::StackOverflow {
+                addr: self.curr_instr_addr,
+            });
+        }
+        self.stack.push(addr);
+        Ok(())
+    }
+
+    pub fn stack_pop(&mut self) -> Result<u16, Chip8Error> {
+        self.stack.pop().ok_or(Chip8Error::StackUnderflow {
+            addr: self.curr_instr_addr,
+        })
+    }
+
+    // return addresses, innermost last
+    pub fn get_stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn load_to_ram(&mut self, instrs: &[u8]) -> Result<(), Chip8Error> {
+        let max = self.ram.len() - PROGRAM_START;
+        if instrs.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: instrs.len(),
+                max,
+            });
+        }
+        // load all the instructions into memory starting at 0x200
+        self.ram[PROGRAM_START..PROGRAM_START + instrs.len()].copy_from_slice(instrs);
+        Ok(())
+    }
+
+    // for debugging, dumps every byte of RAM at the cpu trace level
     pub fn inspect_ram(&self) {
-        println!("-----------------------------------");
-        println!("DEBUG: Printing RAM contents");
+        trace!(self.tracer, Cpu, Trace, "Printing RAM contents");
         for (i, mem) in self.ram.iter().enumerate() {
-            println!("addr: {:03x}, value: {:04x}", i, mem);
+            trace!(
+                self.tracer,
+                Cpu,
+                Trace,
+                "addr: {:03x}, value: {:04x}",
+                i,
+                mem
+            );
         }
     }
-    pub fn stack_push_pc(&mut self) {
-        self.stack_push(self.state.pc);
+    pub fn stack_push_pc(&mut self) -> Result<(), Chip8Error> {
+        self.stack_push(self.state.pc)
     }
 
     pub fn set_pc(&mut self, addr: u16) {
@@ -135,18 +417,22 @@ impl Chip8<'_> {
     }
 
     pub fn incr_pc(&mut self) {
-        self.state.pc += 2
+        self.state.pc = self.state.pc.wrapping_add(2)
     }
 
     pub fn decr_pc(&mut self) {
-        self.state.pc -= 2
+        self.state.pc = self.state.pc.wrapping_sub(2)
+    }
+
+    pub fn get_pc(&self) -> u16 {
+        self.state.pc
     }
 
     pub fn set_index_reg(&mut self, addr: u16) {
         self.state.i_reg = addr;
     }
 
-    pub fn get_index_reg(&mut self) -> u16 {
+    pub fn get_index_reg(&self) -> u16 {
         self.state.i_reg
     }
 
@@ -154,56 +440,120 @@ impl Chip8<'_> {
         self.state.v_regs[reg_num as usize] = val;
     }
 
-    pub fn get_reg(&mut self, reg_num: u8) -> u8 {
+    pub fn get_reg(&self, reg_num: u8) -> u8 {
         self.state.v_regs[reg_num as usize]
     }
 
-    pub fn get_mem_data(&mut self, addr: u16) -> u8 {
-        self.ram[addr as usize]
+    fn out_of_bounds(&self, addr: usize) -> Chip8Error {
+        Chip8Error::MemoryOutOfBounds {
+            addr,
+            pc: self.curr_instr_addr,
+        }
+    }
+
+    // Every access the program makes goes through here so watchpoints see
+    // it. A break watchpoint lets the instruction finish and then stops the
+    // machine until `take_watch_hit`.
+    fn observe(&mut self, kind: AccessKind, addr: u16, value: u8) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+        let access = MemAccess {
+            kind,
+            addr,
+            value,
+            pc: self.curr_instr_addr,
+        };
+        if self.watchpoints.check(&access) && self.watch_hit.is_none() {
+            self.watch_hit = Some(access);
+        }
+    }
+
+    // the access that stopped the machine, taking it lets the machine run on
+    pub fn take_watch_hit(&mut self) -> Option<MemAccess> {
+        self.watch_hit.take()
+    }
+
+    // memory as the host sees it, watchpoints don't hear about these
+    pub fn peek_mem(&self, addr: u16) -> Result<u8, Chip8Error> {
+        self.ram
+            .get(addr as usize)
+            .copied()
+            .ok_or(self.out_of_bounds(addr as usize))
+    }
+
+    pub fn peek_word(&self, addr: u16) -> Result<u16, Chip8Error> {
+        let hi = self.peek_mem(addr)?;
+        let lo = self.peek_mem(addr.wrapping_add(1))?;
+        Ok((hi as u16) << 8 | lo as u16)
+    }
+
+    // a read by the program
+    pub fn get_mem_data(&mut self, addr: u16) -> Result<u8, Chip8Error> {
+        let value = self.peek_mem(addr)?;
+        self.observe(AccessKind::Read, addr, value);
+        Ok(value)
+    }
+
+    // a write by the program
+    pub fn set_mem_data(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        if addr as usize >= self.ram.len() {
+            return Err(self.out_of_bounds(addr as usize));
+        }
+        self.ram[addr as usize] = value;
+        self.observe(AccessKind::Write, addr, value);
+        Ok(())
     }
 
-    pub fn skip_if_key(&mut self, reg_num: u8, is_same: bool) {
+    // an instruction word fetched by the program
+    pub fn fetch_word(&mut self, addr: u16) -> Result<u16, Chip8Error> {
+        let word = self.peek_word(addr)?;
+        self.observe(AccessKind::Execute, addr, (word >> 8) as u8);
+        self.observe(AccessKind::Execute, addr.wrapping_add(1), word as u8);
+        Ok(word)
+    }
+
+    // skip the next instruction, on XO-CHIP that may be the 4-byte F000 NNNN
+    pub fn skip_next(&mut self) -> Result<(), Chip8Error> {
+        if self.platform == Platform::XoChip && self.peek_word(self.state.pc)? == 0xF000 {
+            self.incr_pc();
+        }
+        self.incr_pc();
+        Ok(())
+    }
+
+    pub fn skip_if_key(&mut self, reg_num: u8, is_same: bool) -> Result<(), Chip8Error> {
         // determine if the key in reg_num is currently being held down
         let reg_key = self.get_reg(reg_num);
-        let pressed_key = {
-            match self.key_input.borrow().get_curr_pressed_key() {
-                // some key is being pressed down
-                Some(kc) => {
-                    if let Some(k) = key_input::chip8_keycode_map(kc) {
-                        Some(k)
-                    } else {
-                        return;
-                    }
-                }
-                // no key is currently pressed
-                None => None,
-            }
-        };
-        if let Some(k) = pressed_key {
-            if k == reg_key && is_same {
-                self.incr_pc()
-            }
-            if k != reg_key && !is_same {
-                self.incr_pc()
-            }
-        } else if !is_same {
-            // no key => not same as key in VX
-            self.incr_pc()
+        if self.key_input.is_pressed(reg_key) == is_same {
+            self.skip_next()?;
         }
+        Ok(())
     }
 
-    pub fn store_from_i(&mut self, vals: Vec<u8>) {
+    pub fn store_from_i(&mut self, vals: Vec<u8>) -> Result<(), Chip8Error> {
         // starting at I store values at increasing offsets
         let curr = self.get_index_reg() as usize;
-        self.ram[curr..(curr + vals.len())].copy_from_slice(&vals);
+        let end = curr + vals.len();
+        if end > self.ram.len() {
+            return Err(self.out_of_bounds(end - 1));
+        }
+        for (offset, val) in vals.into_iter().enumerate() {
+            self.set_mem_data((curr + offset) as u16, val)?;
+        }
+        Ok(())
     }
 
-    pub fn load_from_i(&mut self, num_regs: u8) {
+    pub fn load_from_i(&mut self, num_regs: u8) -> Result<(), Chip8Error> {
         let curr_index: u16 = self.get_index_reg();
+        let end = curr_index as usize + num_regs as usize;
+        if end >= self.ram.len() {
+            return Err(self.out_of_bounds(end));
+        }
         for i in 0..num_regs + 1 {
-            let end = curr_index + (i as u16);
-            self.state.v_regs[i as usize] = self.ram[end as usize];
+            self.state.v_regs[i as usize] = self.get_mem_data(curr_index + i as u16)?;
         }
+        Ok(())
     }
 
     pub fn get_regs_in_range(&self, range: u8) -> Vec<u8> {
@@ -212,31 +562,222 @@ impl Chip8<'_> {
     }
 
     pub fn block_till_key(&mut self, reg_num: u8) {
-        let pressed_key = {
-            match self.key_input.borrow().get_curr_pressed_key() {
-                // some key is being pressed down
-                Some(kc) => {
-                    if let Some(k) = key_input::chip8_keycode_map(kc) {
-                        Some(k)
-                    } else {
-                        return;
-                    }
-                }
-                // no key is currently pressed
-                None => None,
-            }
-        };
-        if let Some(k) = pressed_key {
+        // like the COSMAC VIP, a key counts once it has been pressed and
+        // released again after FX0A started waiting
+        if !self.waiting_for_key {
+            self.key_input.clear_released();
+            self.waiting_for_key = true;
+        }
+        if let Some(k) = self.key_input.take_released() {
+            // store the pressed in reg
             self.set_reg(reg_num, k);
+            self.waiting_for_key = false;
         } else {
-            // store the pressed in reg
             self.decr_pc();
         }
     }
 
     pub fn load_char_into_index_reg(&mut self, val: u8) {
-        let addr_of_char = 0x050 + (val * 5);
-        println!("Addr of char: {:04x}", addr_of_char);
-        self.set_index_reg(addr_of_char.into());
+        // only the low nibble selects a character
+        let addr_of_char = FONT_ADDR + (val & 0xF) as u16 * 5;
+        trace!(
+            self.tracer,
+            Cpu,
+            Debug,
+            "Addr of char: {:04x}",
+            addr_of_char
+        );
+        self.set_index_reg(addr_of_char);
+    }
+
+    pub fn load_big_char_into_index_reg(&mut self, val: u8) {
+        // 10 bytes per big character
+        let addr_of_char = BIG_FONT_ADDR + (val & 0xF) as u16 * 10;
+        self.set_index_reg(addr_of_char);
     }
+
+    // Snapshot of the whole machine, see `savestate` for the format. The
+    // tracer, watchpoints, audio sink and fault policy belong to the host and
+    // aren't saved.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.platform(self.platform);
+        w.quirks(&self.quirks);
+        w.bytes(&self.state.v_regs);
+        w.u16(self.state.pc);
+        w.u16(self.state.i_reg);
+        w.u8(self.stack.len() as u8);
+        for addr in &self.stack {
+            w.u16(*addr);
+        }
+        w.bytes(&self.ram);
+        self.display.write_state(&mut w);
+        w.u32(self.delay_timer.get_time_left() as u32);
+        w.u32(self.sound_timer.get_time_left() as u32);
+        self.key_input.write_state(&mut w);
+        w.bool(self.waiting_for_key);
+        w.bool(self.waiting_for_vblank);
+        w.bool(self.exited);
+        w.bool(self.hires);
+        w.u8(self.planes);
+        w.bytes(&self.rpl_flags);
+        w.bool(self.audio_pattern.is_some());
+        w.bytes(&self.audio_pattern.unwrap_or_default());
+        w.u8(self.pitch);
+        w.u16(self.curr_instr_addr);
+        w.u8(self.rng.kind().to_u8());
+        w.u64(self.rng_seed);
+        w.bytes(&self.rng.save());
+        w.finish()
+    }
+
+    // Restores a `save_state` snapshot. Nothing changes unless the whole
+    // state reads back fine, and a machine halted by a fault runs again.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data)?;
+        let platform = r.platform()?;
+        let quirks = r.quirks()?;
+        let v_regs: [u8; 16] = r
+            .bytes()?
+            .try_into()
+            .map_err(|_| StateError::Invalid("expected 16 registers".to_string()))?;
+        let pc = r.u16()?;
+        let i_reg = r.u16()?;
+        let stack_len = r.u8()? as usize;
+        if stack_len > STACK_SIZE {
+            return Err(StateError::Invalid(format!(
+                "stack depth {} is over {}",
+                stack_len, STACK_SIZE
+            )));
+        }
+        let stack = (0..stack_len)
+            .map(|_| r.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+        let ram = r.bytes()?;
+        if ram.len() != platform.memory_size() {
+            return Err(StateError::Invalid(format!(
+                "{} bytes of RAM for {}",
+                ram.len(),
+                platform.name()
+            )));
+        }
+        let display = Framebuffer::read_state(&mut r)?;
+        let delay = r.u32()? as usize;
+        let sound = r.u32()? as usize;
+        let key_input = KeyInput::read_state(&mut r)?;
+        let waiting_for_key = r.bool()?;
+        let waiting_for_vblank = r.bool()?;
+        let exited = r.bool()?;
+        let hires = r.bool()?;
+        let planes = r.u8()?;
+        let rpl_flags = r
+            .bytes()?
+            .try_into()
+            .map_err(|_| StateError::Invalid("expected 16 RPL flags".to_string()))?;
+        let has_pattern = r.bool()?;
+        let pattern: [u8; PATTERN_SIZE] = r
+            .bytes()?
+            .try_into()
+            .map_err(|_| StateError::Invalid("expected a 16 byte audio pattern".to_string()))?;
+        let pitch = r.u8()?;
+        let curr_instr_addr = r.u16()?;
+        let rng_kind = r.u8()?;
+        let rng_kind = RngKind::from_u8(rng_kind).ok_or(StateError::Invalid(format!(
+            "unknown random generator {}",
+            rng_kind
+        )))?;
+        let rng_seed = r.u64()?;
+        let rng_state = r.bytes()?;
+        r.finish()?;
+        // a custom generator can only go back into the one that saved it
+        let rng = match rng_kind.build(rng_seed) {
+            Some(mut rng) => {
+                rng.restore(rng_state).map_err(StateError::Invalid)?;
+                Some(rng)
+            }
+            None if self.rng.kind() == RngKind::Custom => {
+                self.rng.restore(rng_state).map_err(StateError::Invalid)?;
+                None
+            }
+            None => {
+                return Err(StateError::Invalid(
+                    "saved with a custom random generator".to_string(),
+                ))
+            }
+        };
+
+        self.platform = platform;
+        self.quirks = quirks;
+        self.state = CPUState { v_regs, pc, i_reg };
+        self.stack = stack;
+        self.ram = ram.to_vec();
+        self.display = display;
+        self.delay_timer.set_time_left(delay);
+        self.sound_timer.set_time_left(sound);
+        self.key_input = key_input;
+        self.waiting_for_key = waiting_for_key;
+        self.waiting_for_vblank = waiting_for_vblank;
+        self.exited = exited;
+        self.hires = hires;
+        self.planes = planes & 0b11;
+        self.rpl_flags = rpl_flags;
+        self.audio_pattern = has_pattern.then_some(pattern);
+        self.pitch = pitch;
+        self.curr_instr_addr = curr_instr_addr;
+        self.watch_hit = None;
+        if let Some(rng) = rng {
+            self.rng = rng;
+        }
+        self.rng_seed = rng_seed;
+        self.halted = None;
+        Ok(())
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_fault_policies() {
+    // 0x200: RET with an empty stack, 0x202: V0 = 1
+    let rom = [0x00, 0xEE, 0x60, 0x01];
+
+    let mut chip8 = Chip8::new();
+    chip8.load_to_ram(&rom).unwrap();
+    let err = Chip8Error::StackUnderflow { addr: 0x200 };
+    assert_eq!(chip8.cpu_loop(), Err(err.clone()));
+    assert!(chip8.is_halted());
+    assert_eq!(chip8.cpu_loop(), Err(err.clone()));
+    assert_eq!(chip8.get_pc(), 0x200);
+
+    let mut chip8 = Chip8::new();
+    chip8.set_fault_policy(FaultPolicy::Skip);
+    chip8.load_to_ram(&rom).unwrap();
+    assert_eq!(chip8.cpu_loop(), Err(err));
+    assert_eq!(chip8.cpu_loop(), Ok(()));
+    assert_eq!(chip8.get_reg(0), 1);
+
+    let mut chip8 = Chip8::new();
+    chip8.set_fault_policy(FaultPolicy::NoOp);
+    chip8.load_to_ram(&rom).unwrap();
+    assert_eq!(chip8.cpu_loop(), Ok(()));
+
+    // running off the end of memory can't be skipped past
+    let mut chip8 = Chip8::new();
+    chip8.set_fault_policy(FaultPolicy::Skip);
+    chip8.set_pc(0xFFF);
+    assert!(chip8.cpu_loop().is_err());
+    assert!(chip8.is_halted());
+    assert_eq!(chip8.get_pc(), 0xFFF);
+
+    assert_eq!(
+        chip8.load_to_ram(&[0; 4096]),
+        Err(Chip8Error::RomTooLarge {
+            size: 4096,
+            max: 4096 - PROGRAM_START
+        })
+    );
 }
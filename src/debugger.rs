@@ -0,0 +1,349 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::cpu::Chip8;
+use crate::instr::{parse_number, Instruction};
+use crate::scheduler::{Scheduler, FRAME_RATE};
+use crate::watch::Watchpoint;
+
+pub const HELP: &str = "Commands:
+    break [addr]        set a breakpoint, or list them
+    delete <addr>       remove a breakpoint
+    step [n]            run n instructions (default 1)
+    continue            run until a breakpoint, a fault or 00FD
+    frame               run to the next 60 Hz tick
+    regs                registers, timers and the keypad
+    mem <addr> [len]    hex dump of memory (default 16 bytes)
+    stack               return addresses, innermost first
+    set <reg> <val>     set V0-VF, I or PC
+    disasm [addr]       disassemble around PC (or addr)
+    watch [spec]        watch memory, e.g. `0x300-0x302:w:log`, or list them
+    unwatch <addr>      remove the watchpoints covering addr
+    help                this list
+    quit                leave the debugger";
+
+// `continue` gives up after this much emulated time without a break
+const MAX_CONTINUE_FRAMES: u32 = 60 * FRAME_RATE;
+// lines `disasm` shows before and after the address
+const DISASM_BEFORE: u16 = 4;
+const DISASM_AFTER: u16 = 6;
+
+// Command interpreter for `--debug`. Runs the machine through the
+// scheduler's `step` one instruction at a time, so timers still tick at the
+// right instruction counts. Every command returns the text to show, errors
+// are for commands that couldn't be understood.
+pub struct Debugger {
+    scheduler: Scheduler,
+    breakpoints: BTreeSet<u16>,
+}
+
+fn parse_addr(s: &str) -> Result<u16, String> {
+    parse_number(s)
+}
+
+fn parse_reg(s: &str) -> Option<u8> {
+    let upper = s.to_ascii_uppercase();
+    let hex = upper.strip_prefix('V')?;
+    match u8::from_str_radix(hex, 16) {
+        Ok(reg) if hex.len() == 1 => Some(reg),
+        _ => None,
+    }
+}
+
+impl Debugger {
+    pub fn new(ips: u32) -> Debugger {
+        Debugger {
+            scheduler: Scheduler::new(ips),
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn get_breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    // run one command line, an empty line does nothing
+    pub fn command(&mut self, chip8: &mut Chip8, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((cmd, args)) = words.split_first() else {
+            return Ok(String::new());
+        };
+        let arg = |i: usize| args.get(i).copied();
+        match (*cmd, args.len()) {
+            ("break" | "b", 0) => Ok(self.list_breakpoints()),
+            ("break" | "b", 1) => {
+                let addr = parse_addr(args[0])?;
+                self.add_breakpoint(addr);
+                Ok(format!("breakpoint at {:03x}", addr))
+            }
+            ("delete" | "d", 1) => {
+                let addr = parse_addr(args[0])?;
+                match self.remove_breakpoint(addr) {
+                    true => Ok(format!("removed breakpoint at {:03x}", addr)),
+                    false => Err(format!("no breakpoint at {:03x}", addr)),
+                }
+            }
+            ("step" | "s", 0 | 1) => {
+                let count = arg(0).map(parse_number).transpose()?.unwrap_or(1).max(1);
+                Ok(self.run(chip8, |_, steps, _| steps == count as u32))
+            }
+            ("continue" | "c", 0) => Ok(self.run(chip8, |_, _, _| false)),
+            ("frame" | "f", 0) => Ok(self.run(chip8, |_, _, ticked| ticked)),
+            ("regs" | "r", 0) => Ok(regs(chip8)),
+            ("mem" | "m", 1 | 2) => {
+                let addr = parse_addr(args[0])?;
+                let len = arg(1).map(parse_number).transpose()?.unwrap_or(16);
+                mem(chip8, addr, len)
+            }
+            ("stack", 0) => Ok(stack(chip8)),
+            ("set", 2) => set(chip8, args[0], args[1]),
+            ("disasm" | "l", 0 | 1) => {
+                let addr = arg(0).map(parse_addr).transpose()?;
+                Ok(self.disasm(chip8, addr.unwrap_or(chip8.get_pc())))
+            }
+            ("watch" | "w", 0) => Ok(watchpoints(chip8)),
+            ("watch" | "w", 1) => {
+                let watchpoint: Watchpoint = args[0].parse()?;
+                chip8.watchpoints.add(watchpoint);
+                Ok(format!("watching {}", watchpoint))
+            }
+            ("unwatch", 1) => {
+                let addr = parse_addr(args[0])?;
+                match chip8.watchpoints.remove(addr) {
+                    0 => Err(format!("no watchpoint covers {:03x}", addr)),
+                    removed => Ok(format!("removed {} watchpoint(s)", removed)),
+                }
+            }
+            ("help" | "h", _) => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command `{}`, try `help`", line.trim())),
+        }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "no breakpoints".to_string();
+        }
+        let addrs: Vec<String> = self
+            .breakpoints
+            .iter()
+            .map(|addr| format!("{:03x}", addr))
+            .collect();
+        format!("breakpoints: {}", addrs.join(" "))
+    }
+
+    // Step until `done(chip8, steps, ticked)` says so, a breakpoint or
+    // watchpoint is hit, the machine faults or exits, or `continue` runs out
+    // of patience. Says why it stopped and where.
+    fn run<F>(&mut self, chip8: &mut Chip8, mut done: F) -> String
+    where
+        F: FnMut(&Chip8, u32, bool) -> bool,
+    {
+        let mut steps = 0;
+        let mut frames = 0;
+        let reason = loop {
+            if chip8.has_exited() {
+                break "exited".to_string();
+            }
+            let ticked = match self.scheduler.step(chip8) {
+                Ok(ticked) => ticked,
+                Err(err) => break format!("fault: {}", err),
+            };
+            steps += 1;
+            frames += ticked as u32;
+            if let Some(hit) = chip8.take_watch_hit() {
+                break format!("watchpoint: {}", hit);
+            }
+            if done(chip8, steps, ticked) {
+                break String::new();
+            }
+            if self.breakpoints.contains(&chip8.get_pc()) {
+                break "breakpoint".to_string();
+            }
+            if frames == MAX_CONTINUE_FRAMES {
+                break format!("still running after {} frames", frames);
+            }
+        };
+        let mut out = String::new();
+        if !reason.is_empty() {
+            writeln!(out, "{}", reason).unwrap();
+        }
+        write!(out, "{}", self.disasm_line(chip8, chip8.get_pc())).unwrap();
+        out
+    }
+
+    // one instruction with its address and raw bytes, returns its size too
+    fn decode(chip8: &Chip8, addr: u16) -> (String, u16) {
+        let Ok(word) = chip8.peek_word(addr) else {
+            return ("(out of memory)".to_string(), 2);
+        };
+        match Instruction::decode(word) {
+            Ok(Instruction::LoadILong { .. }) => {
+                let long = chip8.peek_word(addr.wrapping_add(2)).unwrap_or(0);
+                let instr = Instruction::LoadILong { addr: long };
+                (format!("{:04x}{:04x}  {}", word, long, instr), 4)
+            }
+            Ok(instr) => (format!("{:04x}      {}", word, instr), 2),
+            Err(_) => (format!("{:04x}      (data)", word), 2),
+        }
+    }
+
+    fn disasm_line(&self, chip8: &Chip8, addr: u16) -> String {
+        let current = if addr == chip8.get_pc() { '>' } else { ' ' };
+        let breakpoint = if self.breakpoints.contains(&addr) {
+            '*'
+        } else {
+            ' '
+        };
+        let (text, _) = Self::decode(chip8, addr);
+        format!("{}{} {:03x}: {}", current, breakpoint, addr, text)
+    }
+
+    // Instructions aren't self-synchronizing, so the lines before `addr` are
+    // decoded on the guess that everything there is 2-byte code.
+    fn disasm(&self, chip8: &Chip8, addr: u16) -> String {
+        let mut lines = Vec::new();
+        let mut at = addr.saturating_sub(DISASM_BEFORE * 2);
+        while at < addr {
+            lines.push(self.disasm_line(chip8, at));
+            at += 2;
+        }
+        let mut at = addr;
+        for _ in 0..=DISASM_AFTER {
+            lines.push(self.disasm_line(chip8, at));
+            at = at.wrapping_add(Self::decode(chip8, at).1);
+        }
+        lines.join("\n")
+    }
+}
+
+fn regs(chip8: &Chip8) -> String {
+    let mut out = String::new();
+    for row in 0..2u8 {
+        let regs: Vec<String> = (row * 8..row * 8 + 8)
+            .map(|reg| format!("V{:X}={:02x}", reg, chip8.get_reg(reg)))
+            .collect();
+        writeln!(out, "{}", regs.join(" ")).unwrap();
+    }
+    let held: Vec<String> = (0..16u8)
+        .filter(|key| chip8.key_input.is_pressed(*key))
+        .map(|key| format!("{:X}", key))
+        .collect();
+    write!(
+        out,
+        "PC={:03x} I={:03x} SP={} DT={} ST={} keys=[{}]",
+        chip8.get_pc(),
+        chip8.get_index_reg(),
+        chip8.get_stack().len(),
+        chip8.delay_timer.get_time_left(),
+        chip8.sound_timer.get_time_left(),
+        held.join(" ")
+    )
+    .unwrap();
+    out
+}
+
+fn mem(chip8: &Chip8, addr: u16, len: u16) -> Result<String, String> {
+    let mut lines = Vec::new();
+    for row_start in (addr as u32..addr as u32 + len as u32).step_by(16) {
+        let row_end = (row_start + 16).min(addr as u32 + len as u32);
+        let bytes = (row_start..row_end)
+            .map(|at| chip8.peek_mem(at as u16).map(|b| format!("{:02x}", b)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| err.to_string())?;
+        lines.push(format!("{:03x}: {}", row_start, bytes.join(" ")));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn stack(chip8: &Chip8) -> String {
+    let stack = chip8.get_stack();
+    if stack.is_empty() {
+        return "stack is empty".to_string();
+    }
+    let lines: Vec<String> = stack
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(depth, addr)| format!("#{} {:03x}", depth, addr))
+        .collect();
+    lines.join("\n")
+}
+
+fn watchpoints(chip8: &Chip8) -> String {
+    let list = chip8.watchpoints.list();
+    if list.is_empty() {
+        return "no watchpoints".to_string();
+    }
+    let specs: Vec<String> = list.iter().map(Watchpoint::to_string).collect();
+    format!("watchpoints: {}", specs.join(" "))
+}
+
+fn set(chip8: &mut Chip8, target: &str, val: &str) -> Result<String, String> {
+    let val = parse_number(val)?;
+    if let Some(reg) = parse_reg(target) {
+        let byte = u8::try_from(val).map_err(|_| format!("{} doesn't fit in a register", val))?;
+        chip8.set_reg(reg, byte);
+        return Ok(format!("V{:X}={:02x}", reg, byte));
+    }
+    match target.to_ascii_uppercase().as_str() {
+        "I" => chip8.set_index_reg(val),
+        "PC" => chip8.set_pc(val),
+        _ => return Err(format!("can't set {}, expected V0-VF, I or PC", target)),
+    }
+    Ok(format!("{}={:03x}", target.to_ascii_uppercase(), val))
+}
+
+#[test]
+fn test_debugger() {
+    // LD V0, 0x05; CALL 0x208; JP 0x204; ADD V0, 1; RET
+    let rom = [
+        0x60, 0x05, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE,
+    ];
+    let mut chip8 = Chip8::new();
+    chip8.load_to_ram(&rom).unwrap();
+    let mut debugger = Debugger::new(600);
+
+    debugger.command(&mut chip8, "break 0x20a").unwrap();
+    let out = debugger.command(&mut chip8, "continue").unwrap();
+    assert!(out.starts_with("breakpoint\n>* 20a: 00ee"), "{}", out);
+    assert_eq!(chip8.get_reg(0), 6);
+    assert_eq!(debugger.command(&mut chip8, "stack").unwrap(), "#0 204");
+
+    debugger.command(&mut chip8, "step 2").unwrap();
+    assert_eq!(chip8.get_pc(), 0x204);
+    debugger.command(&mut chip8, "set V3 0x10").unwrap();
+    debugger.command(&mut chip8, "set i 0x300").unwrap();
+    assert_eq!(chip8.get_reg(3), 0x10);
+    assert_eq!(chip8.get_index_reg(), 0x300);
+    assert_eq!(
+        debugger.command(&mut chip8, "mem 0x200 4").unwrap(),
+        "200: 60 05 22 08"
+    );
+
+    // 10 instructions per frame, 5 have run
+    chip8.delay_timer.set_time_left(2);
+    let out = debugger.command(&mut chip8, "frame").unwrap();
+    assert_eq!(chip8.delay_timer.get_time_left(), 1);
+    assert_eq!(out, ">  204: 1204      JP 0x204");
+    assert!(debugger.command(&mut chip8, "set V3 0x100").is_err());
+
+    // running from the top again calls the ADD at 0x208
+    debugger.command(&mut chip8, "watch 0x208:x").unwrap();
+    debugger.command(&mut chip8, "set PC 0x200").unwrap();
+    let out = debugger.command(&mut chip8, "continue").unwrap();
+    assert!(
+        out.starts_with("watchpoint: execute 208 = 70 at pc 208"),
+        "{}",
+        out
+    );
+    assert_eq!(chip8.get_pc(), 0x20a);
+    assert!(debugger.command(&mut chip8, "jump").is_err());
+}
@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::cpu::PROGRAM_START;
+use crate::instr::Instruction;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    // the mnemonics `Instruction` prints, e.g. `LD V0, 0x05`
+    Classic,
+    // Octo assembly, e.g. `v0 := 0x05`
+    Octo,
+}
+
+// what kind of address a label points at, decides the label name
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Sub,
+}
+
+// result of following control flow through a ROM loaded at 0x200
+struct Analysis {
+    // code[i] = an instruction starts at PROGRAM_START + i
+    code: Vec<bool>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+fn decode_at(rom: &[u8], addr: u16) -> Option<Instruction> {
+    let offset = (addr as usize).checked_sub(PROGRAM_START)?;
+    let word = |offset: usize| {
+        let bytes = rom.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    };
+    match Instruction::decode(word(offset)?) {
+        // 0NNN is almost always padding or sprite data in practice
+        Ok(Instruction::Sys { .. }) | Err(_) => None,
+        Ok(Instruction::LoadILong { .. }) => Some(Instruction::LoadILong {
+            addr: word(offset + 2)?,
+        }),
+        Ok(instr) => Some(instr),
+    }
+}
+
+fn in_rom(rom: &[u8], addr: u16) -> bool {
+    (PROGRAM_START..PROGRAM_START + rom.len()).contains(&(addr as usize))
+}
+
+// Walk every path from 0x200 through jumps, calls and skips. Anything never
+// reached is treated as data. BNNN only marks its base address, the table
+// it jumps into can't be followed without knowing V0.
+fn analyze(rom: &[u8]) -> Analysis {
+    use Instruction::*;
+    let mut code = vec![false; rom.len()];
+    let mut labels = BTreeMap::new();
+    let add_label = |labels: &mut BTreeMap<u16, LabelKind>, addr: u16, kind| {
+        if in_rom(rom, addr) {
+            let old = labels.entry(addr).or_insert(kind);
+            *old = kind.max(*old);
+        }
+    };
+
+    let mut todo = vec![PROGRAM_START as u16];
+    while let Some(addr) = todo.pop() {
+        // targets outside the ROM stay plain numbers
+        if !in_rom(rom, addr) {
+            continue;
+        }
+        let offset = addr as usize - PROGRAM_START;
+        if code[offset] {
+            continue;
+        }
+        let Some(instr) = decode_at(rom, addr) else {
+            continue;
+        };
+        code[offset] = true;
+
+        let next = addr.wrapping_add(instr.size());
+        match instr {
+            Ret | Exit => {}
+            Jump { addr: target } => {
+                add_label(&mut labels, target, LabelKind::Jump);
+                todo.push(target);
+            }
+            JumpV0 { addr: target } => {
+                add_label(&mut labels, target, LabelKind::Jump);
+                todo.push(target);
+            }
+            Call { addr: target } => {
+                add_label(&mut labels, target, LabelKind::Sub);
+                todo.push(target);
+                todo.push(next);
+            }
+            LoadI { addr: target } | LoadILong { addr: target } => {
+                add_label(&mut labels, target, LabelKind::Data);
+                todo.push(next);
+            }
+            SkipEqByte { .. }
+            | SkipNeByte { .. }
+            | SkipEqReg { .. }
+            | SkipNeReg { .. }
+            | SkipKey { .. }
+            | SkipNotKey { .. } => {
+                // skipping F000 NNNN jumps over all 4 bytes
+                let skipped = decode_at(rom, next).map_or(2, |instr| instr.size());
+                todo.push(next);
+                todo.push(next.wrapping_add(skipped));
+            }
+            _ => todo.push(next),
+        }
+    }
+
+    Analysis { code, labels }
+}
+
+// one line of output, either an instruction or a run of data bytes
+enum Item {
+    Code(Instruction),
+    Data(Vec<u8>),
+}
+
+const DATA_PER_LINE: usize = 8;
+
+struct Disassembler<'a> {
+    rom: &'a [u8],
+    syntax: Syntax,
+    items: Vec<(u16, Item)>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl<'a> Disassembler<'a> {
+    fn new(rom: &'a [u8], syntax: Syntax) -> Disassembler<'a> {
+        let analysis = analyze(rom);
+
+        // lay out instructions and data, an instruction overlapping the one
+        // before it (jump into the middle) gets emitted as data instead
+        let mut items: Vec<(u16, Item)> = Vec::new();
+        let mut offset = 0;
+        while offset < rom.len() {
+            let addr = (PROGRAM_START + offset) as u16;
+            if analysis.code[offset] {
+                let instr = decode_at(rom, addr).unwrap();
+                offset += instr.size() as usize;
+                items.push((addr, Item::Code(instr)));
+                continue;
+            }
+            let starts_line = match items.last() {
+                Some((_, Item::Data(bytes))) => {
+                    bytes.len() == DATA_PER_LINE || analysis.labels.contains_key(&addr)
+                }
+                _ => true,
+            };
+            if starts_line {
+                items.push((addr, Item::Data(Vec::new())));
+            }
+            if let Some((_, Item::Data(bytes))) = items.last_mut() {
+                bytes.push(rom[offset]);
+            }
+            offset += 1;
+        }
+
+        // only addresses a line starts at can carry a label
+        let labels = items
+            .iter()
+            .filter_map(|(addr, _)| {
+                let name = match analysis.labels.get(addr)? {
+                    LabelKind::Data => "data",
+                    LabelKind::Jump => "label",
+                    LabelKind::Sub => "sub",
+                };
+                Some((*addr, format!("{}_{:03x}", name, addr)))
+            })
+            .collect();
+
+        Disassembler {
+            rom,
+            syntax,
+            items,
+            labels,
+        }
+    }
+
+    fn target(&self, addr: u16) -> String {
+        match self.labels.get(&addr) {
+            Some(label) => label.clone(),
+            None => format!("0x{:03x}", addr),
+        }
+    }
+
+    fn classic(&self, instr: &Instruction) -> String {
+        use Instruction::*;
+        match *instr {
+            Jump { addr } => format!("JP {}", self.target(addr)),
+            JumpV0 { addr } => format!("JP V0, {}", self.target(addr)),
+            Call { addr } => format!("CALL {}", self.target(addr)),
+            LoadI { addr } => format!("LD I, {}", self.target(addr)),
+            LoadILong { addr } => format!("LDL I, {}", self.target(addr)),
+            _ => instr.to_string(),
+        }
+    }
+
+    fn octo(&self, instr: &Instruction) -> String {
+        use Instruction::*;
+        match *instr {
+            Sys { addr } => format!("0x{:02x} 0x{:02x}", addr >> 8, addr & 0xFF),
+            Cls => "clear".to_string(),
+            Ret => "return".to_string(),
+            Jump { addr } => format!("jump {}", self.target(addr)),
+            JumpV0 { addr } => format!("jump0 {}", self.target(addr)),
+            Call { addr } => match self.labels.get(&addr) {
+                Some(label) => label.clone(),
+                None => format!(":call 0x{:03x}", addr),
+            },
+            // a skip runs the next instruction when its condition is false
+            SkipEqByte { x, nn } => format!("if v{:x} != 0x{:02x} then", x, nn),
+            SkipNeByte { x, nn } => format!("if v{:x} == 0x{:02x} then", x, nn),
+            SkipEqReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+            SkipNeReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+            SkipKey { x } => format!("if v{:x} -key then", x),
+            SkipNotKey { x } => format!("if v{:x} key then", x),
+            LoadByte { x, nn } => format!("v{:x} := 0x{:02x}", x, nn),
+            AddByte { x, nn } => format!("v{:x} += 0x{:02x}", x, nn),
+            LoadReg { x, y } => format!("v{:x} := v{:x}", x, y),
+            Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+            And { x, y } => format!("v{:x} &= v{:x}", x, y),
+            Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+            AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+            Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+            SubN { x, y } => format!("v{:x} =- v{:x}", x, y),
+            ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+            ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+            LoadI { addr } => format!("i := {}", self.target(addr)),
+            Random { x, nn } => format!("v{:x} := random 0x{:02x}", x, nn),
+            Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+            LoadDelay { x } => format!("v{:x} := delay", x),
+            WaitKey { x } => format!("v{:x} := key", x),
+            SetDelay { x } => format!("delay := v{:x}", x),
+            SetSound { x } => format!("buzzer := v{:x}", x),
+            AddI { x } => format!("i += v{:x}", x),
+            LoadFont { x } => format!("i := hex v{:x}", x),
+            Bcd { x } => format!("bcd v{:x}", x),
+            Store { x } => format!("save v{:x}", x),
+            Load { x } => format!("load v{:x}", x),
+            ScrollDown { n } => format!("scroll-down {}", n),
+            ScrollRight => "scroll-right".to_string(),
+            ScrollLeft => "scroll-left".to_string(),
+            Exit => "exit".to_string(),
+            Lores => "lores".to_string(),
+            Hires => "hires".to_string(),
+            LoadBigFont { x } => format!("i := bighex v{:x}", x),
+            SaveFlags { x } => format!("saveflags v{:x}", x),
+            LoadFlags { x } => format!("loadflags v{:x}", x),
+            LoadILong { addr } => format!("i := long {}", self.target(addr)),
+            Plane { n } => format!("plane {}", n),
+            LoadAudio => "audio".to_string(),
+            SetPitch { x } => format!("pitch := v{:x}", x),
+            SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+            LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        }
+    }
+
+    fn render(&self) -> String {
+        let comment = match self.syntax {
+            Syntax::Classic => ';',
+            Syntax::Octo => '#',
+        };
+        let mut out = String::new();
+        for (addr, item) in &self.items {
+            if let Some(label) = self.labels.get(addr) {
+                match self.syntax {
+                    Syntax::Classic => writeln!(out, "{}:", label).unwrap(),
+                    Syntax::Octo => writeln!(out, ": {}", label).unwrap(),
+                }
+            }
+            let offset = *addr as usize - PROGRAM_START;
+            let (text, raw) = match item {
+                Item::Code(instr) => {
+                    let text = match self.syntax {
+                        Syntax::Classic => self.classic(instr),
+                        Syntax::Octo => self.octo(instr),
+                    };
+                    (text, &self.rom[offset..offset + instr.size() as usize])
+                }
+                Item::Data(bytes) => {
+                    let vals: Vec<String> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+                    let text = match self.syntax {
+                        Syntax::Classic => format!("DB {}", vals.join(", ")),
+                        Syntax::Octo => vals.join(" "),
+                    };
+                    (text, bytes.as_slice())
+                }
+            };
+            let raw: Vec<String> = raw.iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(
+                out,
+                "    {:<28} {} {:03x}: {}",
+                text,
+                comment,
+                addr,
+                raw.join("")
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+// Disassemble a ROM as it sits in memory from 0x200. Control flow decides
+// what is code, jump/call/I targets get labels, and every line ends in a
+// comment with its address and raw bytes.
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    Disassembler::new(rom, syntax).render()
+}
+
+#[test]
+fn test_disassemble_code_and_data() {
+    let rom = [
+        0x22, 0x08, // 200: CALL sub_208
+        0xA2, 0x0C, // 202: LD I, data_20c
+        0x12, 0x02, // 204: JP label_202
+        0xFF, 0xFF, // 206: never reached
+        0xD0, 0x15, // 208: DRW V0, V1, 5
+        0x00, 0xEE, // 20a: RET
+        0xF0, 0x90, // 20c: sprite data
+    ];
+    let out = disassemble(&rom, Syntax::Classic);
+    let lines: Vec<&str> = out
+        .lines()
+        .map(|l| l.split(';').next().unwrap().trim())
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "CALL sub_208",
+            "label_202:",
+            "LD I, data_20c",
+            "JP label_202",
+            "DB 0xff, 0xff",
+            "sub_208:",
+            "DRW V0, V1, 0x5",
+            "RET",
+            "data_20c:",
+            "DB 0xf0, 0x90",
+        ]
+    );
+    assert!(disassemble(&rom, Syntax::Octo).contains("    sub_208 "));
+}
+
+#[test]
+fn test_disassemble_targets_outside_rom() {
+    // below 0x200, e.g. a VIP machine code routine
+    let out = disassemble(&[0x11, 0x00], Syntax::Classic);
+    assert!(out.contains("JP 0x100 "));
+    let rom = [
+        0x22, 0x04, // 200: CALL 0x204, just past the end
+        0xB3, 0x00, // 202: JP V0, 0x300
+    ];
+    let out = disassemble(&rom, Syntax::Classic);
+    assert!(out.contains("CALL 0x204 "));
+    assert!(out.contains("JP V0, 0x300 "));
+    assert!(disassemble(&rom, Syntax::Octo).contains(":call 0x204 "));
+}
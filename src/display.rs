@@ -1,30 +1,33 @@
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use std::io::{self, Write};
 
-const RECT_HEIGHT: u32 = 10;
-const RECT_WIDTH: u32 = 10;
+use crate::savestate::{StateError, StateReader, StateWriter};
+use crate::screenshot;
+use crate::video::Palette;
+use crate::{HIRES_COLS, HIRES_ROWS, NUM_COLS, NUM_ROWS};
 
-pub struct Display {
-    canvas: Canvas<Window>,
+// pixel state of the CHIP-8 screen, each cell is 0 (off) or 1 (on), or on
+// XO-CHIP a 2-bit mask of the bitplanes that are lit; how it gets shown is up
+// to a `video::VideoSink`
+pub struct Framebuffer {
     pub num_rows: usize,
     pub num_cols: usize,
     buffer: Vec<Vec<u8>>,
     should_update: bool,
 }
 
-fn canvas_init(canvas: &mut Canvas<Window>) {
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
+// ' ' off, 'X' on, and on XO-CHIP '+' for only plane 2 and '#' for both
+pub fn grid_symbol(cell: u8) -> char {
+    match cell {
+        0 => ' ',
+        1 => 'X',
+        2 => '+',
+        _ => '#',
+    }
 }
 
-impl Display {
-    pub fn new(num_rows: usize, num_cols: usize, mut canvas: Canvas<Window>) -> Display {
-        canvas_init(&mut canvas);
-        Display {
-            canvas,
+impl Framebuffer {
+    pub fn new(num_rows: usize, num_cols: usize) -> Framebuffer {
+        Framebuffer {
             num_rows,
             num_cols,
             buffer: vec![vec![0; num_cols]; num_rows],
@@ -32,45 +35,61 @@ impl Display {
         }
     }
 
-    pub fn draw_grid(&mut self) {
-        for (i, row) in self.buffer.iter().enumerate() {
-            for (j, &cell) in row.iter().enumerate() {
-                let color = if cell == 0 {
-                    Color::RGB(0, 0, 0)
-                } else {
-                    Color::RGB(255, 255, 255)
-                };
-                self.canvas.set_draw_color(color);
-                let rect = Rect::new(j as i32 * 10, i as i32 * 10, RECT_WIDTH, RECT_HEIGHT);
-                self.canvas.fill_rect(rect).unwrap();
-            }
-        }
-        self.canvas.present();
-    }
-
     #[allow(dead_code)]
     pub fn pretty_print_display_grid(&self) {
         println!("-----------------------------------");
         println!("DEBUG: Printing DISPLAY GRID");
+        print!("{}", self.render_grid());
+    }
+
+    // one line per row, see `grid_symbol`
+    pub fn render_grid(&self) -> String {
+        let mut out = String::with_capacity((self.num_cols + 1) * self.num_rows);
         for row in &self.buffer {
-            for cell in row {
-                let symbol = if *cell == 1 { 'X' } else { ' ' };
-                print!("{}", symbol);
-            }
-            println!();
+            out.extend(row.iter().map(|cell| grid_symbol(*cell)));
+            out.push('\n');
         }
+        out
     }
-    pub fn display_update(&mut self) {
-        if self.should_update {
-            // self.pretty_print_display_grid();
-            self.draw_grid();
-            self.should_update = false;
+
+    // see `screenshot` for the formats
+    pub fn write_png<W: Write>(&self, palette: &Palette, scale: u32, out: W) -> io::Result<()> {
+        screenshot::write_png(self, palette, scale, out)
+    }
+
+    pub fn write_pbm<W: Write>(&self, out: W) -> io::Result<()> {
+        screenshot::write_pbm(self, out)
+    }
+
+    // FNV-1a over the size and every cell, same as `rom::rom_hash`
+    pub fn hash(&self) -> u64 {
+        let mut bytes = Vec::with_capacity(4 + self.num_rows * self.num_cols);
+        bytes.extend_from_slice(&(self.num_rows as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.num_cols as u16).to_le_bytes());
+        for row in &self.buffer {
+            bytes.extend_from_slice(row);
         }
+        crate::rom::rom_hash(&bytes)
+    }
+
+    // returns whether the buffer changed since the last call, so a frontend
+    // only has to redraw when something was actually drawn
+    pub fn display_update(&mut self) -> bool {
+        let should_update = self.should_update;
+        self.should_update = false;
+        should_update
     }
 
     pub fn clear_display(&mut self) {
         self.buffer = vec![vec![0; self.num_cols]; self.num_rows];
-        canvas_init(&mut self.canvas);
+        self.should_update = true;
+    }
+
+    // 00E0 on XO-CHIP only clears the selected planes
+    pub fn clear_planes(&mut self, planes: u8) {
+        for cell in self.buffer.iter_mut().flatten() {
+            *cell &= !planes;
+        }
         self.should_update = true;
     }
 
@@ -82,4 +101,109 @@ impl Display {
     pub fn get_display_buffer(&mut self, row: u8, col: u8) -> u8 {
         self.buffer[row as usize][col as usize]
     }
+
+    pub fn buffer(&self) -> &[Vec<u8>] {
+        &self.buffer
+    }
+
+    // switch resolution (SUPER-CHIP lores/hires), which clears the screen
+    pub fn resize(&mut self, num_rows: usize, num_cols: usize) {
+        self.num_rows = num_rows;
+        self.num_cols = num_cols;
+        self.clear_display();
+    }
+
+    // scrolling only moves the given planes, the others stay put
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.num_rows);
+        let mut scrolled = self.buffer.clone();
+        scrolled.rotate_right(n);
+        for row in &mut scrolled[..n] {
+            row.fill(0);
+        }
+        self.merge_planes(scrolled, planes);
+    }
+
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.num_cols);
+        let mut scrolled = self.buffer.clone();
+        for row in &mut scrolled {
+            row.rotate_right(n);
+            row[..n].fill(0);
+        }
+        self.merge_planes(scrolled, planes);
+    }
+
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.num_cols);
+        let num_cols = self.num_cols;
+        let mut scrolled = self.buffer.clone();
+        for row in &mut scrolled {
+            row.rotate_left(n);
+            row[num_cols - n..].fill(0);
+        }
+        self.merge_planes(scrolled, planes);
+    }
+
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.u16(self.num_rows as u16);
+        w.u16(self.num_cols as u16);
+        w.bytes(&self.buffer.concat());
+    }
+
+    pub(crate) fn read_state(r: &mut StateReader) -> Result<Framebuffer, StateError> {
+        let num_rows = r.u16()? as usize;
+        let num_cols = r.u16()? as usize;
+        let cells = r.bytes()?;
+        let lores = (NUM_ROWS as usize, NUM_COLS as usize);
+        let hires = (HIRES_ROWS as usize, HIRES_COLS as usize);
+        if ![lores, hires].contains(&(num_rows, num_cols)) {
+            return Err(StateError::Invalid(format!(
+                "{}x{} isn't a lores or hires display",
+                num_cols, num_rows
+            )));
+        }
+        if cells.len() != num_rows * num_cols {
+            return Err(StateError::Invalid(format!(
+                "{} pixels don't make a {}x{} display",
+                cells.len(),
+                num_cols,
+                num_rows
+            )));
+        }
+        Ok(Framebuffer {
+            num_rows,
+            num_cols,
+            buffer: cells.chunks(num_cols).map(<[u8]>::to_vec).collect(),
+            // whatever is on screen now is stale
+            should_update: true,
+        })
+    }
+
+    fn merge_planes(&mut self, scrolled: Vec<Vec<u8>>, planes: u8) {
+        for (cell, new) in self
+            .buffer
+            .iter_mut()
+            .flatten()
+            .zip(scrolled.iter().flatten())
+        {
+            *cell = (*cell & !planes) | (new & planes);
+        }
+        self.should_update = true;
+    }
+}
+
+#[test]
+fn test_scroll() {
+    let mut fb = Framebuffer::new(4, 8);
+    fb.set_display(0, 0, 0b11);
+    fb.scroll_down(2, 0b11);
+    fb.scroll_right(4, 0b11);
+    assert_eq!(fb.get_display_buffer(2, 4), 0b11);
+    // plane 2 stays behind
+    fb.scroll_left(4, 0b01);
+    assert_eq!(fb.get_display_buffer(2, 0), 0b01);
+    assert_eq!(fb.get_display_buffer(2, 4), 0b10);
+    fb.clear_planes(0b10);
+    assert_eq!(fb.buffer().iter().flatten().sum::<u8>(), 1);
 }
@@ -0,0 +1,66 @@
+use std::fmt;
+use std::str::FromStr;
+
+// everything that can go wrong while loading or running a ROM
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    // opcode that doesn't decode to any instruction, found at addr
+    InvalidOpcode { opcode: u16, addr: u16 },
+    // CALL with all stack slots in use
+    StackOverflow { addr: u16 },
+    // RET with nothing on the stack
+    StackUnderflow { addr: u16 },
+    // the instruction at pc tried to touch memory past the end of RAM
+    MemoryOutOfBounds { addr: usize, pc: u16 },
+    RomTooLarge { size: usize, max: usize },
+    RomRead { path: String, reason: String },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidOpcode { opcode, addr } => {
+                write!(f, "invalid opcode {:04x} at {:03x}", opcode, addr)
+            }
+            Chip8Error::StackOverflow { addr } => write!(f, "stack overflow at {:03x}", addr),
+            Chip8Error::StackUnderflow { addr } => write!(f, "stack underflow at {:03x}", addr),
+            Chip8Error::MemoryOutOfBounds { addr, pc } => {
+                write!(f, "out of bounds memory access to {:x} at {:03x}", addr, pc)
+            }
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, at most {} fit in memory", size, max)
+            }
+            Chip8Error::RomRead { path, reason } => {
+                write!(f, "could not read ROM {}: {}", path, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+// what the interpreter does when an instruction faults
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FaultPolicy {
+    // stop the machine, every later `cpu_loop` returns the same error
+    #[default]
+    Halt,
+    // report the error but leave the machine runnable past the instruction
+    // (fetching from outside memory still halts)
+    Skip,
+    // silently treat the faulting instruction as a no-op
+    NoOp,
+}
+
+impl FromStr for FaultPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "halt" => Ok(FaultPolicy::Halt),
+            "skip" => Ok(FaultPolicy::Skip),
+            "nop" | "noop" => Ok(FaultPolicy::NoOp),
+            _ => Err(format!("unknown fault policy {} (halt, skip, nop)", s)),
+        }
+    }
+}
@@ -1,5 +1,9 @@
-use crate::{cpu::Chip8, utils};
-use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::quirks::Platform;
+use crate::trace::trace;
+use crate::{cpu::Chip8, error::Chip8Error, utils};
 
 fn first_nib(opcode: &u16) -> u16 {
     (opcode & 0xF000) >> 12
@@ -51,357 +55,959 @@ fn test_opcode_helpers() {
     assert_eq!(most_significant_bit(&x), 1);
 }
 
-// 2 byte long integer in rust
-pub fn op(opcode: u16, chip8: &mut Chip8) {
-    // first nibble extracted by masking out last 3 nibbles
-    // then bit shift by 12 (12 bits, i.e. 3 hex digits)
-    println!("DEBUG: opcode: {:04x}", opcode);
-    let first_nibble = first_nib(&opcode);
-    match first_nibble {
-        0x0 => op_0(opcode, chip8),
-        0x1 => op_1(opcode, chip8),
-        0x2 => op_2(opcode, chip8),
-        0x3 => op_3(opcode, chip8),
-        0x4 => op_4(opcode, chip8),
-        0x5 => op_5(opcode, chip8),
-        0x6 => op_6(opcode, chip8),
-        0x7 => op_7(opcode, chip8),
-        0x8 => op_8(opcode, chip8),
-        0x9 => op_9(opcode, chip8),
-        0xa => op_a(opcode, chip8),
-        0xb => op_b(opcode, chip8),
-        0xc => op_c(opcode, chip8),
-        0xd => op_d(opcode, chip8),
-        0xe => op_e(opcode, chip8),
-        0xf => op_f(opcode, chip8),
-        _ => println!("error: invalid opcode"),
-    };
-    println!();
+// One CHIP-8 instruction. `decode`/`encode` convert from/to the 16-bit
+// opcode, `Display`/`FromStr` use the classic (Cowgod) mnemonics, so the
+// interpreter, debugger and disassembler all agree on what an opcode means.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Sys { addr: u16 },            // 0NNN: SYS addr (machine code, unsupported)
+    Cls,                          // 00E0: CLS
+    Ret,                          // 00EE: RET
+    ScrollDown { n: u8 },         // 00CN: SCD nibble (SUPER-CHIP)
+    ScrollRight,                  // 00FB: SCR (SUPER-CHIP)
+    ScrollLeft,                   // 00FC: SCL (SUPER-CHIP)
+    Exit,                         // 00FD: EXIT (SUPER-CHIP)
+    Lores,                        // 00FE: LOW (SUPER-CHIP)
+    Hires,                        // 00FF: HIGH (SUPER-CHIP)
+    Jump { addr: u16 },           // 1NNN: JP addr
+    Call { addr: u16 },           // 2NNN: CALL addr
+    SkipEqByte { x: u8, nn: u8 }, // 3XNN: SE Vx, byte
+    SkipNeByte { x: u8, nn: u8 }, // 4XNN: SNE Vx, byte
+    SkipEqReg { x: u8, y: u8 },   // 5XY0: SE Vx, Vy
+    SaveRange { x: u8, y: u8 },   // 5XY2: SAVE Vx, Vy (XO-CHIP)
+    LoadRange { x: u8, y: u8 },   // 5XY3: LOAD Vx, Vy (XO-CHIP)
+    LoadByte { x: u8, nn: u8 },   // 6XNN: LD Vx, byte
+    AddByte { x: u8, nn: u8 },    // 7XNN: ADD Vx, byte
+    LoadReg { x: u8, y: u8 },     // 8XY0: LD Vx, Vy
+    Or { x: u8, y: u8 },          // 8XY1: OR Vx, Vy
+    And { x: u8, y: u8 },         // 8XY2: AND Vx, Vy
+    Xor { x: u8, y: u8 },         // 8XY3: XOR Vx, Vy
+    AddReg { x: u8, y: u8 },      // 8XY4: ADD Vx, Vy
+    Sub { x: u8, y: u8 },         // 8XY5: SUB Vx, Vy
+    ShiftRight { x: u8, y: u8 },  // 8XY6: SHR Vx, Vy
+    SubN { x: u8, y: u8 },        // 8XY7: SUBN Vx, Vy
+    ShiftLeft { x: u8, y: u8 },   // 8XYE: SHL Vx, Vy
+    SkipNeReg { x: u8, y: u8 },   // 9XY0: SNE Vx, Vy
+    LoadI { addr: u16 },          // ANNN: LD I, addr
+    JumpV0 { addr: u16 },         // BNNN: JP V0, addr
+    Random { x: u8, nn: u8 },     // CXNN: RND Vx, byte
+    Draw { x: u8, y: u8, n: u8 }, // DXYN: DRW Vx, Vy, nibble
+    SkipKey { x: u8 },            // EX9E: SKP Vx
+    SkipNotKey { x: u8 },         // EXA1: SKNP Vx
+    LoadDelay { x: u8 },          // FX07: LD Vx, DT
+    WaitKey { x: u8 },            // FX0A: LD Vx, K
+    SetDelay { x: u8 },           // FX15: LD DT, Vx
+    SetSound { x: u8 },           // FX18: LD ST, Vx
+    AddI { x: u8 },               // FX1E: ADD I, Vx
+    LoadFont { x: u8 },           // FX29: LD F, Vx
+    Bcd { x: u8 },                // FX33: LD B, Vx
+    Store { x: u8 },              // FX55: LD [I], Vx
+    Load { x: u8 },               // FX65: LD Vx, [I]
+    LoadBigFont { x: u8 },        // FX30: LD HF, Vx (SUPER-CHIP)
+    SaveFlags { x: u8 },          // FX75: LD R, Vx (SUPER-CHIP)
+    LoadFlags { x: u8 },          // FX85: LD Vx, R (SUPER-CHIP)
+    LoadILong { addr: u16 },      // F000 NNNN: LDL I, addr (XO-CHIP)
+    Plane { n: u8 },              // FN01: PLANE n (XO-CHIP)
+    LoadAudio,                    // F002: AUDIO (XO-CHIP)
+    SetPitch { x: u8 },           // FX3A: PITCH Vx (XO-CHIP)
+}
+
+// opcode that isn't any CHIP-8 instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
 }
 
-fn op_0(opcode: u16, chip8: &mut Chip8) {
-    // extract the last 3 nibbles
-    let instr = second_byte(&opcode);
-    match instr {
-        0xE0 => {
-            println!("00E0: Clear Screen");
-            chip8.display.clear_display()
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid opcode {:04x}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        use Instruction::*;
+        // first nibble extracted by masking out last 3 nibbles
+        // then bit shift by 12 (12 bits, i.e. 3 hex digits)
+        let x = second_nib(&opcode) as u8;
+        let y = third_nib(&opcode) as u8;
+        let n = fourth_nib(&opcode) as u8;
+        let nn = second_byte(&opcode) as u8;
+        let addr = addr_bits(&opcode);
+        let invalid = Err(DecodeError { opcode });
+
+        let instr = match first_nib(&opcode) {
+            0x0 => match addr {
+                0x0E0 => Cls,
+                0x0EE => Ret,
+                0x0C0..=0x0CF => ScrollDown { n },
+                0x0FB => ScrollRight,
+                0x0FC => ScrollLeft,
+                0x0FD => Exit,
+                0x0FE => Lores,
+                0x0FF => Hires,
+                _ => Sys { addr },
+            },
+            0x1 => Jump { addr },
+            0x2 => Call { addr },
+            0x3 => SkipEqByte { x, nn },
+            0x4 => SkipNeByte { x, nn },
+            0x5 => match n {
+                0x0 => SkipEqReg { x, y },
+                0x2 => SaveRange { x, y },
+                0x3 => LoadRange { x, y },
+                _ => return invalid,
+            },
+            0x6 => LoadByte { x, nn },
+            0x7 => AddByte { x, nn },
+            0x8 => match n {
+                0x0 => LoadReg { x, y },
+                0x1 => Or { x, y },
+                0x2 => And { x, y },
+                0x3 => Xor { x, y },
+                0x4 => AddReg { x, y },
+                0x5 => Sub { x, y },
+                0x6 => ShiftRight { x, y },
+                0x7 => SubN { x, y },
+                0xe => ShiftLeft { x, y },
+                _ => return invalid,
+            },
+            0x9 if n == 0 => SkipNeReg { x, y },
+            0xa => LoadI { addr },
+            0xb => JumpV0 { addr },
+            0xc => Random { x, nn },
+            0xd => Draw { x, y, n },
+            0xe => match nn {
+                0x9e => SkipKey { x },
+                0xa1 => SkipNotKey { x },
+                _ => return invalid,
+            },
+            0xf => match nn {
+                0x07 => LoadDelay { x },
+                0x0a => WaitKey { x },
+                0x15 => SetDelay { x },
+                0x18 => SetSound { x },
+                0x1e => AddI { x },
+                0x29 => LoadFont { x },
+                0x33 => Bcd { x },
+                0x55 => Store { x },
+                0x65 => Load { x },
+                0x30 => LoadBigFont { x },
+                0x75 => SaveFlags { x },
+                0x85 => LoadFlags { x },
+                // the address is the word after the opcode, see `op`
+                0x00 if x == 0 => LoadILong { addr: 0 },
+                0x01 => Plane { n: x },
+                0x02 if x == 0 => LoadAudio,
+                0x3a => SetPitch { x },
+                _ => return invalid,
+            },
+            _ => return invalid,
+        };
+        Ok(instr)
+    }
+
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+        let xy = |base: u16, x: &u8, y: &u8, n: u16| base | (*x as u16) << 8 | (*y as u16) << 4 | n;
+        let xnn = |base: u16, x: &u8, nn: u16| base | (*x as u16) << 8 | nn;
+        match self {
+            Sys { addr } => *addr & 0xFFF,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            ScrollDown { n } => 0x00C0 | (*n as u16 & 0xF),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Lores => 0x00FE,
+            Hires => 0x00FF,
+            Jump { addr } => 0x1000 | (addr & 0xFFF),
+            Call { addr } => 0x2000 | (addr & 0xFFF),
+            SkipEqByte { x, nn } => xnn(0x3000, x, *nn as u16),
+            SkipNeByte { x, nn } => xnn(0x4000, x, *nn as u16),
+            SkipEqReg { x, y } => xy(0x5000, x, y, 0),
+            LoadByte { x, nn } => xnn(0x6000, x, *nn as u16),
+            AddByte { x, nn } => xnn(0x7000, x, *nn as u16),
+            LoadReg { x, y } => xy(0x8000, x, y, 0x0),
+            Or { x, y } => xy(0x8000, x, y, 0x1),
+            And { x, y } => xy(0x8000, x, y, 0x2),
+            Xor { x, y } => xy(0x8000, x, y, 0x3),
+            AddReg { x, y } => xy(0x8000, x, y, 0x4),
+            Sub { x, y } => xy(0x8000, x, y, 0x5),
+            ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            SubN { x, y } => xy(0x8000, x, y, 0x7),
+            ShiftLeft { x, y } => xy(0x8000, x, y, 0xe),
+            SkipNeReg { x, y } => xy(0x9000, x, y, 0),
+            LoadI { addr } => 0xA000 | (addr & 0xFFF),
+            JumpV0 { addr } => 0xB000 | (addr & 0xFFF),
+            Random { x, nn } => xnn(0xC000, x, *nn as u16),
+            Draw { x, y, n } => xy(0xD000, x, y, *n as u16 & 0xF),
+            SkipKey { x } => xnn(0xE000, x, 0x9e),
+            SkipNotKey { x } => xnn(0xE000, x, 0xa1),
+            LoadDelay { x } => xnn(0xF000, x, 0x07),
+            WaitKey { x } => xnn(0xF000, x, 0x0a),
+            SetDelay { x } => xnn(0xF000, x, 0x15),
+            SetSound { x } => xnn(0xF000, x, 0x18),
+            AddI { x } => xnn(0xF000, x, 0x1e),
+            LoadFont { x } => xnn(0xF000, x, 0x29),
+            Bcd { x } => xnn(0xF000, x, 0x33),
+            Store { x } => xnn(0xF000, x, 0x55),
+            Load { x } => xnn(0xF000, x, 0x65),
+            LoadBigFont { x } => xnn(0xF000, x, 0x30),
+            SaveFlags { x } => xnn(0xF000, x, 0x75),
+            LoadFlags { x } => xnn(0xF000, x, 0x85),
+            LoadILong { .. } => 0xF000,
+            Plane { n } => xnn(0xF000, n, 0x01),
+            LoadAudio => 0xF002,
+            SetPitch { x } => xnn(0xF000, x, 0x3A),
+            SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            LoadRange { x, y } => xy(0x5000, x, y, 0x3),
         }
-        0xEE => {
-            // return
-            let ret_addr = chip8.stack_pop();
-            println!("00EE: Return to {:03x}", ret_addr);
-            chip8.set_pc(ret_addr);
+    }
+
+    // size in memory, F000 NNNN is the only 4-byte instruction
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadILong { .. } => 4,
+            _ => 2,
         }
-        // TODO: better error handling
-        _ => panic!("Error: Invalid opcode"),
+    }
+
+    // big-endian bytes as stored in memory
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LoadILong { addr } = self {
+            bytes.extend_from_slice(&addr.to_be_bytes());
+        }
+        bytes
+    }
+
+    // only runs on SUPER-CHIP (and XO-CHIP)
+    pub fn is_schip(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            ScrollDown { .. }
+                | ScrollRight
+                | ScrollLeft
+                | Exit
+                | Lores
+                | Hires
+                | LoadBigFont { .. }
+                | SaveFlags { .. }
+                | LoadFlags { .. }
+        )
+    }
+
+    // only runs on XO-CHIP
+    pub fn is_xochip(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            SaveRange { .. }
+                | LoadRange { .. }
+                | LoadILong { .. }
+                | Plane { .. }
+                | LoadAudio
+                | SetPitch { .. }
+        )
     }
 }
 
-fn op_1(opcode: u16, chip8: &mut Chip8) {
-    let addr = addr_bits(&opcode);
-    println!("{:04x}: JUMP {:03x}", opcode, addr);
-    chip8.set_pc(addr);
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        match self {
+            Sys { addr } => write!(f, "SYS 0x{:03x}", addr),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            ScrollDown { n } => write!(f, "SCD 0x{:x}", n),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Lores => write!(f, "LOW"),
+            Hires => write!(f, "HIGH"),
+            Jump { addr } => write!(f, "JP 0x{:03x}", addr),
+            Call { addr } => write!(f, "CALL 0x{:03x}", addr),
+            SkipEqByte { x, nn } => write!(f, "SE V{:X}, 0x{:02x}", x, nn),
+            SkipNeByte { x, nn } => write!(f, "SNE V{:X}, 0x{:02x}", x, nn),
+            SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            LoadByte { x, nn } => write!(f, "LD V{:X}, 0x{:02x}", x, nn),
+            AddByte { x, nn } => write!(f, "ADD V{:X}, 0x{:02x}", x, nn),
+            LoadReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadI { addr } => write!(f, "LD I, 0x{:03x}", addr),
+            JumpV0 { addr } => write!(f, "JP V0, 0x{:03x}", addr),
+            Random { x, nn } => write!(f, "RND V{:X}, 0x{:02x}", x, nn),
+            Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, 0x{:x}", x, y, n),
+            SkipKey { x } => write!(f, "SKP V{:X}", x),
+            SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            LoadDelay { x } => write!(f, "LD V{:X}, DT", x),
+            WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            AddI { x } => write!(f, "ADD I, V{:X}", x),
+            LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Store { x } => write!(f, "LD [I], V{:X}", x),
+            Load { x } => write!(f, "LD V{:X}, [I]", x),
+            LoadBigFont { x } => write!(f, "LD HF, V{:X}", x),
+            SaveFlags { x } => write!(f, "LD R, V{:X}", x),
+            LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            LoadILong { addr } => write!(f, "LDL I, 0x{:04x}", addr),
+            Plane { n } => write!(f, "PLANE 0x{:x}", n),
+            LoadAudio => write!(f, "AUDIO"),
+            SetPitch { x } => write!(f, "PITCH V{:X}", x),
+            SaveRange { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            LoadRange { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+        }
+    }
 }
 
-fn op_2(opcode: u16, chip8: &mut Chip8) {
-    let addr = opcode & 0x0FFF;
-    println!("{:04x}: CALL {:03x}", opcode, addr);
-    // push addr to call stack
-    chip8.stack_push_pc();
-    // jump
-    chip8.set_pc(addr);
+// a parsed operand of a mnemonic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+    Reg(u8),
+    Num(u16),
+    I,
+    IndirectI,
+    Delay,
+    Sound,
+    Key,
+    Font,
+    BigFont,
+    Flags,
+    Bcd,
 }
 
-fn op_3(opcode: u16, chip8: &mut Chip8) {
-    let reg_num = second_nib(&opcode) as u8;
-    let nn = second_byte(&opcode) as u8;
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    let upper = s.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Delay,
+        "ST" => Operand::Sound,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::BigFont,
+        "R" => Operand::Flags,
+        "B" => Operand::Bcd,
+        _ => {
+            if let Some(reg) = upper.strip_prefix('V') {
+                // exactly one hex digit
+                return match u8::from_str_radix(reg, 16) {
+                    Ok(reg) if reg < 16 => Ok(Operand::Reg(reg)),
+                    _ => Err(format!("invalid register {}", s)),
+                };
+            }
+            Operand::Num(parse_number(s)?)
+        }
+    };
+    Ok(operand)
+}
 
-    let val = chip8.get_reg(reg_num);
-    println!("{:04x}: SKIP IF {} (V{}) == {}", opcode, val, reg_num, nn);
-    if val == nn {
-        chip8.incr_pc();
+// 0x1F, #1F, 0b0101 or decimal
+pub fn parse_number(s: &str) -> Result<u16, String> {
+    let lower = s.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x").or(lower.strip_prefix('#')) {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u16::from_str_radix(bin, 2)
+    } else {
+        lower.parse()
+    };
+    parsed.map_err(|_| format!("invalid number {}", s))
+}
+
+fn check_range(val: u16, max: u16, s: &str) -> Result<u16, String> {
+    if val > max {
+        return Err(format!(
+            "{} does not fit in {} bits",
+            s,
+            16 - max.leading_zeros()
+        ));
     }
+    Ok(val)
 }
 
-fn op_4(opcode: u16, chip8: &mut Chip8) {
-    let reg_num = second_nib(&opcode) as u8;
-    let nn = second_byte(&opcode) as u8;
+// An instruction from its mnemonic and operand texts. Errors also say which
+// operand is at fault, None when it's the instruction as a whole.
+pub(crate) fn parse_instruction(
+    mnemonic: &str,
+    operands: &[&str],
+) -> Result<Instruction, (Option<usize>, String)> {
+    use Instruction::*;
+    use Operand::*;
+    let parsed = operands
+        .iter()
+        .enumerate()
+        .map(|(i, o)| parse_operand(o).map_err(|err| (Some(i), err)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let fits = |i: usize, val: u16, max: u16| {
+        check_range(val, max, operands[i]).map_err(|err| (Some(i), err))
+    };
+    let addr = |i, a| fits(i, a, 0xFFF);
+    let byte = |i, b| fits(i, b, 0xFF).map(|b| b as u8);
+    let nibble = |i, n| fits(i, n, 0xF).map(|n| n as u8);
+
+    let instr = match (mnemonic.to_ascii_uppercase().as_str(), parsed.as_slice()) {
+        ("SYS", [Num(a)]) => Sys { addr: addr(0, *a)? },
+        ("CLS", []) => Cls,
+        ("RET", []) => Ret,
+        ("SCD", [Num(n)]) => ScrollDown { n: nibble(0, *n)? },
+        ("SCR", []) => ScrollRight,
+        ("SCL", []) => ScrollLeft,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => Lores,
+        ("HIGH", []) => Hires,
+        ("JP", [Num(a)]) => Jump { addr: addr(0, *a)? },
+        ("JP", [Reg(0), Num(a)]) => JumpV0 { addr: addr(1, *a)? },
+        ("CALL", [Num(a)]) => Call { addr: addr(0, *a)? },
+        ("SE", [Reg(x), Num(nn)]) => SkipEqByte {
+            x: *x,
+            nn: byte(1, *nn)?,
+        },
+        ("SE", [Reg(x), Reg(y)]) => SkipEqReg { x: *x, y: *y },
+        ("SNE", [Reg(x), Num(nn)]) => SkipNeByte {
+            x: *x,
+            nn: byte(1, *nn)?,
+        },
+        ("SNE", [Reg(x), Reg(y)]) => SkipNeReg { x: *x, y: *y },
+        ("LD", [Reg(x), Num(nn)]) => LoadByte {
+            x: *x,
+            nn: byte(1, *nn)?,
+        },
+        ("LD", [Reg(x), Reg(y)]) => LoadReg { x: *x, y: *y },
+        ("LD", [I, Num(a)]) => LoadI { addr: addr(1, *a)? },
+        ("LD", [Reg(x), Delay]) => LoadDelay { x: *x },
+        ("LD", [Reg(x), Key]) => WaitKey { x: *x },
+        ("LD", [Delay, Reg(x)]) => SetDelay { x: *x },
+        ("LD", [Sound, Reg(x)]) => SetSound { x: *x },
+        ("LD", [Font, Reg(x)]) => LoadFont { x: *x },
+        ("LD", [Operand::Bcd, Reg(x)]) => Instruction::Bcd { x: *x },
+        ("LD", [IndirectI, Reg(x)]) => Store { x: *x },
+        ("LD", [Reg(x), IndirectI]) => Load { x: *x },
+        ("LD", [BigFont, Reg(x)]) => LoadBigFont { x: *x },
+        ("LD", [Flags, Reg(x)]) => SaveFlags { x: *x },
+        ("LD", [Reg(x), Flags]) => LoadFlags { x: *x },
+        ("ADD", [Reg(x), Num(nn)]) => AddByte {
+            x: *x,
+            nn: byte(1, *nn)?,
+        },
+        ("ADD", [Reg(x), Reg(y)]) => AddReg { x: *x, y: *y },
+        ("ADD", [I, Reg(x)]) => AddI { x: *x },
+        ("OR", [Reg(x), Reg(y)]) => Or { x: *x, y: *y },
+        ("AND", [Reg(x), Reg(y)]) => And { x: *x, y: *y },
+        ("XOR", [Reg(x), Reg(y)]) => Xor { x: *x, y: *y },
+        ("SUB", [Reg(x), Reg(y)]) => Sub { x: *x, y: *y },
+        ("SUBN", [Reg(x), Reg(y)]) => SubN { x: *x, y: *y },
+        // the Y operand of the shifts is optional
+        ("SHR", [Reg(x)]) => ShiftRight { x: *x, y: *x },
+        ("SHR", [Reg(x), Reg(y)]) => ShiftRight { x: *x, y: *y },
+        ("SHL", [Reg(x)]) => ShiftLeft { x: *x, y: *x },
+        ("SHL", [Reg(x), Reg(y)]) => ShiftLeft { x: *x, y: *y },
+        ("RND", [Reg(x), Num(nn)]) => Random {
+            x: *x,
+            nn: byte(1, *nn)?,
+        },
+        ("DRW", [Reg(x), Reg(y), Num(n)]) => Draw {
+            x: *x,
+            y: *y,
+            n: nibble(2, *n)?,
+        },
+        ("SKP", [Reg(x)]) => SkipKey { x: *x },
+        ("SKNP", [Reg(x)]) => SkipNotKey { x: *x },
+        ("LDL", [I, Num(a)]) => LoadILong { addr: *a },
+        ("PLANE", [Num(n)]) => Plane { n: nibble(0, *n)? },
+        ("AUDIO", []) => LoadAudio,
+        ("PITCH", [Reg(x)]) => SetPitch { x: *x },
+        ("SAVE", [Reg(x), Reg(y)]) => SaveRange { x: *x, y: *y },
+        ("LOAD", [Reg(x), Reg(y)]) => LoadRange { x: *x, y: *y },
+        _ => {
+            let text = format!("{} {}", mnemonic, operands.join(", "));
+            return Err((None, format!("unknown instruction {}", text.trim())));
+        }
+    };
+    Ok(instr)
+}
+
+impl FromStr for Instruction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (mnemonic, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let operands: Vec<&str> = rest
+            .split(',')
+            .map(str::trim)
+            .filter(|o| !o.is_empty())
+            .collect();
+        parse_instruction(mnemonic, &operands).map_err(|(_, err)| err)
+    }
+}
 
-    let val = chip8.get_reg(reg_num);
-    println!("{:04x}: SKIP IF {} (V{}) != {}", opcode, val, reg_num, nn);
-    if val != nn {
+// decode and run a single opcode
+pub fn op(opcode: u16, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+    trace!(chip8.tracer, Cpu, Trace, "opcode: {:04x}", opcode);
+    let mut instr = Instruction::decode(opcode).map_err(|err| invalid(err.opcode, chip8))?;
+    // before fetching F000's operand, elsewhere it's a plain 2 byte opcode
+    if !runs_on(&instr, chip8.get_platform()) {
+        return Err(invalid(opcode, chip8));
+    }
+    if let Instruction::LoadILong { addr } = &mut instr {
+        *addr = chip8.fetch_word(chip8.get_pc())?;
         chip8.incr_pc();
     }
+    execute(&instr, chip8)
 }
 
-fn op_5(opcode: u16, chip8: &mut Chip8) {
-    let reg_x = second_nib(&opcode) as u8;
-    let reg_y = third_nib(&opcode) as u8;
+// SUPER-CHIP and XO-CHIP opcodes are invalid on the platforms before them
+fn runs_on(instr: &Instruction, platform: Platform) -> bool {
+    !(instr.is_schip() && !platform.has_schip_instrs()
+        || instr.is_xochip() && platform != Platform::XoChip)
+}
 
-    let x = chip8.get_reg(reg_x);
-    let y = chip8.get_reg(reg_y);
-    println!(
-        "{:04x}: SKIP IF {} (V{}) != {} (V{})",
-        opcode, x, reg_x, y, reg_y
-    );
-    if x == y {
-        chip8.incr_pc();
+fn invalid(opcode: u16, chip8: &Chip8) -> Chip8Error {
+    Chip8Error::InvalidOpcode {
+        opcode,
+        addr: chip8.get_curr_instr_addr(),
     }
 }
 
-fn op_6(opcode: u16, chip8: &mut Chip8) {
-    let reg_num = second_nib(&opcode);
-    let val = second_byte(&opcode);
-    println!("{:04x}: SET V{} = {}", opcode, reg_num, val);
-    chip8.set_reg(reg_num as u8, val as u8);
-}
-
-fn op_7(opcode: u16, chip8: &mut Chip8) {
-    let reg_num = second_nib(&opcode);
-    let val = second_byte(&opcode) as u8;
-    let reg_val = chip8.get_reg(reg_num as u8);
-    println!("{:04x}: V{} += {}", opcode, reg_num, val);
-    let res = reg_val.wrapping_add(val);
-    chip8.set_reg(reg_num as u8, res);
-}
-
-fn op_8(opcode: u16, chip8: &mut Chip8) {
-    let reg_x = second_nib(&opcode) as u8;
-    let reg_y = third_nib(&opcode) as u8;
-    let x = chip8.get_reg(reg_x);
-    let y = chip8.get_reg(reg_y);
-
-    match fourth_nib(&opcode) as u8 {
-        0 => {
-            println!("{:04x}: V{} = V{} ({} = {})", opcode, reg_x, reg_y, x, y);
-            chip8.set_reg(reg_x, y)
-        }
-        1 => {
-            println!("{:04x}: V{} | V{} ({} | {})", opcode, reg_x, reg_y, x, y);
-            chip8.set_reg(reg_x, x | y)
-        }
-        2 => {
-            println!("{:04x}: V{} & V{} ({} & {})", opcode, reg_x, reg_y, x, y);
-            chip8.set_reg(reg_x, x & y)
-        }
-        3 => {
-            println!("{:04x}: V{} ^ V{} ({} ^ {})", opcode, reg_x, reg_y, x, y);
-            chip8.set_reg(reg_x, x ^ y)
-        }
-        4 => {
-            let res = x as u16 + y as u16;
-            if res > 255 {
-                chip8.set_reg(reg_x, (res % 256) as u8);
-                chip8.set_reg(0xF, 1);
-                println!(
-                    "{:04x}: V{} = V{} + V{} ({} + {}); VF = {}",
-                    opcode, reg_x, reg_x, reg_y, x, y, 1
-                );
-            } else {
-                chip8.set_reg(reg_x, res as u8);
-                chip8.set_reg(0xF, 0);
-                println!(
-                    "{:04x}: V{} = V{} + V{} ({} + {}); VF = {}",
-                    opcode, reg_x, reg_x, reg_y, x, y, 0
-                );
+// 8XY4/8XY5/8XY7/8XY6/8XYE: write the result first and the flag last, so
+// VF as the destination ends up holding the flag
+fn set_with_flag(chip8: &mut Chip8, x: u8, val: u8, flag: u8) {
+    chip8.set_reg(x, val);
+    chip8.set_reg(0xF, flag);
+}
+
+pub fn execute(instr: &Instruction, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+    use Instruction::*;
+    trace!(
+        chip8.tracer,
+        Cpu,
+        Debug,
+        "{:03x}: {}",
+        chip8.get_curr_instr_addr(),
+        instr
+    );
+    if !runs_on(instr, chip8.get_platform()) {
+        return Err(invalid(instr.encode(), chip8));
+    }
+    match *instr {
+        // machine code routines can't run on anything but a COSMAC VIP
+        Sys { .. } => return Err(invalid(instr.encode(), chip8)),
+        Cls => {
+            trace!(chip8.tracer, Display, Debug, "Clear Screen");
+            chip8.display.clear_planes(chip8.get_planes())
+        }
+        Ret => {
+            let ret_addr = chip8.stack_pop()?;
+            chip8.set_pc(ret_addr);
+        }
+        ScrollDown { n } => chip8.display.scroll_down(n as usize, chip8.get_planes()),
+        ScrollRight => chip8.display.scroll_right(4, chip8.get_planes()),
+        ScrollLeft => chip8.display.scroll_left(4, chip8.get_planes()),
+        Exit => chip8.exit(),
+        Lores => chip8.set_hires(false),
+        Hires => chip8.set_hires(true),
+        Jump { addr } => chip8.set_pc(addr),
+        Call { addr } => {
+            // push addr to call stack
+            chip8.stack_push_pc()?;
+            // jump
+            chip8.set_pc(addr);
+        }
+        SkipEqByte { x, nn } => {
+            if chip8.get_reg(x) == nn {
+                chip8.skip_next()?;
             }
         }
-        5 => {
-            chip8.set_reg(0xF, 1);
-            if x < y {
-                // carry will occur
-                println!(
-                    "{:04x}: V{} = V{} - V{} ({} - {}); VF = {}",
-                    opcode, reg_x, reg_x, reg_y, x, y, 0
-                );
-                chip8.set_reg(0xF, 0);
-            } else {
-                println!(
-                    "{:04x}: V{} = V{} - V{} ({} - {}); VF = {}",
-                    opcode, reg_x, reg_x, reg_y, x, y, 1
-                );
+        SkipNeByte { x, nn } => {
+            if chip8.get_reg(x) != nn {
+                chip8.skip_next()?;
+            }
+        }
+        SkipEqReg { x, y } => {
+            if chip8.get_reg(x) == chip8.get_reg(y) {
+                chip8.skip_next()?;
+            }
+        }
+        SkipNeReg { x, y } => {
+            if chip8.get_reg(x) != chip8.get_reg(y) {
+                chip8.skip_next()?;
             }
-            // ensure proper wrap
-            chip8.set_reg(reg_x, x.wrapping_sub(y));
-        }
-        6 => {
-            let lsb = least_significant_bit(&x);
-            chip8.set_reg(0xF, lsb);
-            chip8.set_reg(reg_x, x >> 1);
-            println!("{:04x}: V{} >> 1, VF = LSB = {}", opcode, reg_x, lsb);
-        }
-        7 => {
-            chip8.set_reg(0xF, 1);
-            if y < x {
-                // carry will occur
-                chip8.set_reg(0xF, 0);
-                println!(
-                    "{:04x}: V{} = V{} - V{} ({} - {}); VF = {}",
-                    opcode, reg_x, reg_y, reg_x, y, x, 0
-                );
+        }
+        LoadByte { x, nn } => chip8.set_reg(x, nn),
+        AddByte { x, nn } => chip8.set_reg(x, chip8.get_reg(x).wrapping_add(nn)),
+        LoadReg { x, y } => chip8.set_reg(x, chip8.get_reg(y)),
+        Or { x, y } => logic(chip8, x, chip8.get_reg(x) | chip8.get_reg(y)),
+        And { x, y } => logic(chip8, x, chip8.get_reg(x) & chip8.get_reg(y)),
+        Xor { x, y } => logic(chip8, x, chip8.get_reg(x) ^ chip8.get_reg(y)),
+        AddReg { x, y } => {
+            let (res, carry) = chip8.get_reg(x).overflowing_add(chip8.get_reg(y));
+            set_with_flag(chip8, x, res, carry as u8);
+        }
+        Sub { x, y } => {
+            // VF = 1 when there is no borrow
+            let (res, borrow) = chip8.get_reg(x).overflowing_sub(chip8.get_reg(y));
+            set_with_flag(chip8, x, res, !borrow as u8);
+        }
+        SubN { x, y } => {
+            let (res, borrow) = chip8.get_reg(y).overflowing_sub(chip8.get_reg(x));
+            set_with_flag(chip8, x, res, !borrow as u8);
+        }
+        ShiftRight { x, y } => {
+            let val = chip8.get_reg(shift_source(chip8, x, y));
+            set_with_flag(chip8, x, val >> 1, least_significant_bit(&val));
+        }
+        ShiftLeft { x, y } => {
+            let val = chip8.get_reg(shift_source(chip8, x, y));
+            set_with_flag(chip8, x, val << 1, most_significant_bit(&val));
+        }
+        LoadI { addr } => chip8.set_index_reg(addr),
+        JumpV0 { addr } => {
+            // BXNN: the high nibble of the address doubles as the register
+            let reg = if chip8.get_quirks().jump_with_vx {
+                (addr >> 8) as u8
             } else {
-                println!(
-                    "{:04x}: V{} = V{} - V{} ({} - {}); VF = {}",
-                    opcode, reg_x, reg_y, reg_x, y, x, 1
-                );
+                0x0
+            };
+            chip8.set_pc(chip8.get_reg(reg) as u16 + addr)
+        }
+        Random { x, nn } => {
+            // random number gen
+            let r = chip8.random_byte();
+            chip8.set_reg(x, r & nn);
+        }
+        Draw { x, y, n } => draw(chip8, x, y, n)?,
+        // skip if key ops (delegate to the cpu)
+        SkipKey { x } => chip8.skip_if_key(x, true)?,
+        SkipNotKey { x } => chip8.skip_if_key(x, false)?,
+        // timers
+        LoadDelay { x } => chip8.set_reg(x, chip8.delay_timer.get_time_left() as u8),
+        SetDelay { x } => {
+            let val = chip8.get_reg(x);
+            trace!(chip8.tracer, Timers, Debug, "DELAY = {}", val);
+            chip8.delay_timer.set_time_left(val.into())
+        }
+        SetSound { x } => {
+            let val = chip8.get_reg(x);
+            trace!(chip8.tracer, Timers, Debug, "SOUND = {}", val);
+            chip8.sound_timer.set_time_left(val.into())
+        }
+        // block until key input
+        WaitKey { x } => chip8.block_till_key(x),
+        // add to index
+        AddI { x } => {
+            let res = chip8.get_index_reg().wrapping_add(chip8.get_reg(x) as u16);
+            chip8.set_index_reg(res);
+        }
+        // index_reg I is set to address of hex char in VX
+        LoadFont { x } => chip8.load_char_into_index_reg(chip8.get_reg(x)),
+        // binary-coded decimal conversion, always 3 digits starting at I
+        Bcd { x } => {
+            let digits = utils::extract_digits_u8(chip8.get_reg(x));
+            let mut bcd = vec![0; 3 - digits.len()];
+            bcd.extend(digits);
+            chip8.store_from_i(bcd)?;
+        }
+        Store { x } => {
+            let vals = chip8.get_regs_in_range(x);
+            chip8.store_from_i(vals)?;
+            increment_i(chip8, x);
+        }
+        Load { x } => {
+            chip8.load_from_i(x)?;
+            increment_i(chip8, x);
+        }
+        LoadBigFont { x } => chip8.load_big_char_into_index_reg(chip8.get_reg(x)),
+        SaveFlags { x } => chip8.save_rpl_flags(x),
+        LoadFlags { x } => chip8.load_rpl_flags(x),
+        LoadILong { addr } => chip8.set_index_reg(addr),
+        Plane { n } => chip8.set_planes(n),
+        LoadAudio => chip8.load_audio_pattern()?,
+        SetPitch { x } => chip8.set_pitch(chip8.get_reg(x)),
+        // like FX55/FX65 but for any range (also backwards), I stays put
+        SaveRange { x, y } => {
+            let vals = reg_range(x, y).map(|r| chip8.get_reg(r)).collect();
+            chip8.store_from_i(vals)?;
+        }
+        LoadRange { x, y } => {
+            for (offset, r) in reg_range(x, y).enumerate() {
+                let addr = chip8.get_index_reg().wrapping_add(offset as u16);
+                let val = chip8.get_mem_data(addr)?;
+                chip8.set_reg(r, val);
             }
-            // ensure proper wrap
-            chip8.set_reg(reg_x, y.wrapping_sub(x));
-        }
-        0xe => {
-            let msb = most_significant_bit(&x);
-            chip8.set_reg(0xF, msb);
-            chip8.set_reg(reg_x, x << 1);
-            println!(
-                "{:04x}: V{} << 1 ({} << 1), VF = MSB = {}",
-                opcode, reg_x, x, msb
-            );
-        }
-        _ => panic!("Error: Invalid op8 type"),
-    };
+        }
+    }
+    Ok(())
+}
 
-    // set reg_x to be reg_y
+// 5XY2/5XY3 walk from X to Y in either direction
+fn reg_range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
 }
-fn op_9(opcode: u16, chip8: &mut Chip8) {
-    let reg_x = second_nib(&opcode) as u8;
-    let reg_y = third_nib(&opcode) as u8;
 
-    let x = chip8.get_reg(reg_x);
-    let y = chip8.get_reg(reg_y);
-    if x != y {
-        chip8.incr_pc();
+// 8XY1/8XY2/8XY3
+fn logic(chip8: &mut Chip8, x: u8, val: u8) {
+    chip8.set_reg(x, val);
+    if chip8.get_quirks().vf_reset {
+        chip8.set_reg(0xF, 0);
+    }
+}
+
+// which register 8XY6/8XYE shift
+fn shift_source(chip8: &Chip8, x: u8, y: u8) -> u8 {
+    if chip8.get_quirks().shift_uses_vy {
+        y
+    } else {
+        x
     }
 }
-fn op_a(opcode: u16, chip8: &mut Chip8) {
-    let addr = addr_bits(&opcode);
-    chip8.set_index_reg(addr);
-}
-fn op_b(opcode: u16, chip8: &mut Chip8) {
-    let addr = addr_bits(&opcode);
-    let v0 = chip8.get_reg(0x0) as u16;
-    chip8.set_pc(v0 + addr);
-}
-fn op_c(opcode: u16, chip8: &mut Chip8) {
-    // random number gen
-    let r: u8 = rand::thread_rng().gen();
-    let reg_x = second_nib(&opcode) as u8;
-    let nn = second_byte(&opcode) as u8;
-    chip8.set_reg(reg_x, r & nn);
-}
-fn op_d(opcode: u16, chip8: &mut Chip8) {
-    let num_rows = chip8.display.num_rows as u8;
-    let num_cols = chip8.display.num_cols as u8;
-
-    let x_reg = second_nib(&opcode);
-    let y_reg = third_nib(&opcode);
-    let x_start = chip8.get_reg(x_reg as u8) & (num_cols - 1); // modulo 64
-    let mut y = chip8.get_reg(y_reg as u8) & (num_rows - 1);
+
+// FX55/FX65
+fn increment_i(chip8: &mut Chip8, x: u8) {
+    if chip8.get_quirks().load_store_increments_i {
+        let i_reg = chip8.get_index_reg().wrapping_add(x as u16 + 1);
+        chip8.set_index_reg(i_reg);
+    }
+}
+
+fn draw(chip8: &mut Chip8, x_reg: u8, y_reg: u8, n: u8) -> Result<(), Chip8Error> {
+    let num_rows = chip8.display.num_rows;
+    let num_cols = chip8.display.num_cols;
+    // DXY0 is a 16x16 sprite (two bytes per row) on SUPER-CHIP
+    let (width, height) = if n == 0 && chip8.get_platform().has_schip_instrs() {
+        (16, 16)
+    } else {
+        (8, n as usize)
+    };
+    let bytes_per_row = width / 8;
+
+    // the start position always wraps (modulo 64 / 128)
+    let x_start = chip8.get_reg(x_reg) as usize % num_cols;
+    let y_start = chip8.get_reg(y_reg) as usize % num_rows;
     chip8.set_reg(0xF, 0); // set VF to 0
 
     let i_reg = chip8.get_index_reg();
-    let n = fourth_nib(&opcode);
-    for i in 0..n {
-        if y == num_rows {
-            break;
-        }
-        let sprite_data: u8 = chip8.get_mem_data(i_reg + i);
-        // from most to least significant
-        let mut x = x_start;
-        for i in 0..8 {
-            if x == num_cols {
+    trace!(
+        chip8.tracer,
+        Display,
+        Debug,
+        "DRAW {}x{} from {:03x} at ({}, {})",
+        width,
+        height,
+        i_reg,
+        x_start,
+        y_start
+    );
+    let clip = chip8.get_quirks().clip_sprites;
+    // rows that hit a lit pixel
+    let mut collided_rows = 0;
+    // rows that fell off the bottom when clipping
+    let mut clipped_rows = 0;
+    // XO-CHIP draws one sprite per selected plane, stored back to back
+    let mut sprite_addr = i_reg;
+    for plane in [1, 2] {
+        if chip8.get_planes() & plane == 0 {
+            continue;
+        }
+        // sprites are either clipped at the bottom and right edges or wrap around
+        for i in 0..height {
+            let y = y_start + i;
+            if clip && y >= num_rows {
+                clipped_rows += height - i;
                 break;
             }
-            let curr_val = chip8.display.get_display_buffer(y, x);
-            let curr_bit = (sprite_data >> (7 - i)) & 1;
-
-            // XOR with carry flag
-            if (curr_val & curr_bit) > 0 {
-                chip8.display.set_display(y, x, 0);
-                chip8.set_reg(0xF, 1);
-            } else if curr_bit > 0 && curr_val == 0 {
-                chip8.display.set_display(y, x, 1);
+            let y = (y % num_rows) as u8;
+            let mut sprite_row: u16 = 0;
+            for byte in 0..bytes_per_row {
+                let addr = sprite_addr.wrapping_add((i * bytes_per_row + byte) as u16);
+                sprite_row = sprite_row << 8 | chip8.get_mem_data(addr)? as u16;
+            }
+            let mut row_collided = false;
+            // from most to least significant
+            for bit in 0..width {
+                let x = x_start + bit;
+                if clip && x >= num_cols {
+                    break;
+                }
+                let x = (x % num_cols) as u8;
+                if (sprite_row >> (width - 1 - bit)) & 1 == 0 {
+                    continue;
+                }
+                // XOR this plane's bit, with carry flag
+                let curr_val = chip8.display.get_display_buffer(y, x);
+                row_collided |= curr_val & plane > 0;
+                chip8.display.set_display(y, x, curr_val ^ plane);
             }
-            x += 1;
+            collided_rows += row_collided as usize;
         }
-        y += 1;
+        sprite_addr = sprite_addr.wrapping_add((height * bytes_per_row) as u16);
     }
+
+    // SUPER-CHIP hires reports how many rows collided or were clipped,
+    // everything else just whether any pixel was turned off
+    let flag = if chip8.get_platform() == Platform::SuperChip && chip8.is_hires() {
+        (collided_rows + clipped_rows) as u8
+    } else {
+        (collided_rows > 0) as u8
+    };
+    chip8.set_reg(0xF, flag);
+    if chip8.get_quirks().display_wait {
+        chip8.wait_for_vblank();
+    }
+    Ok(())
 }
 
-// skip if key ops (delegate to the cpu)
-fn op_e(opcode: u16, chip8: &mut Chip8) {
-    let reg_num = second_nib(&opcode) as u8;
-    match second_byte(&opcode) {
-        0x9e => chip8.skip_if_key(reg_num, true),
-        0xa1 => chip8.skip_if_key(reg_num, false),
-        _ => panic!("Error: Invalid instruction"),
+#[test]
+fn test_instruction_round_trip() {
+    // every opcode that decodes encodes back to itself and survives a trip
+    // through its mnemonic
+    for opcode in 0..=u16::MAX {
+        if let Ok(instr) = Instruction::decode(opcode) {
+            assert_eq!(instr.encode(), opcode);
+            assert_eq!(instr.to_string().parse::<Instruction>(), Ok(instr));
+        }
     }
+    assert_eq!(
+        Instruction::decode(0x5121),
+        Err(DecodeError { opcode: 0x5121 })
+    );
+    assert_eq!(
+        "ld v3, #1f".parse::<Instruction>(),
+        Ok(Instruction::LoadByte { x: 3, nn: 0x1f })
+    );
+    assert!("LD V3, 0x100".parse::<Instruction>().is_err());
+    assert!("LD VG, 1".parse::<Instruction>().is_err());
 }
-fn op_f(opcode: u16, chip8: &mut Chip8) {
-    let reg_num = second_nib(&opcode) as u8;
-    let reg_val = chip8.get_reg(reg_num);
-    match second_byte(&opcode) {
-        // timers
-        0x07 => {
-            // set VX = delay_timer
-            chip8.set_reg(reg_num, chip8.delay_timer.get_time_left() as u8);
+
+#[test]
+fn test_quirks() {
+    // V1 = 0x81, V2 = 0x02; SHR V1, V2; BNNN 0x210
+    let rom = [0x61, 0x81, 0x62, 0x02, 0x81, 0x26, 0xB2, 0x10];
+    let run = |platform: Platform| {
+        let mut chip8 = Chip8::with_platform(platform);
+        chip8.load_to_ram(&rom).unwrap();
+        for _ in 0..4 {
+            chip8.cpu_loop().unwrap();
         }
-        0x15 => chip8.delay_timer.set_time_left(reg_val.into()),
-        0x18 => chip8.sound_timer.set_time_left(reg_val.into()),
+        chip8
+    };
 
-        // add to index
-        0x1e => {
-            let i_reg_val = chip8.get_index_reg();
-            let res = i_reg_val + reg_val as u16;
-            chip8.set_index_reg(res);
+    let chip8 = run(Platform::Chip8);
+    assert_eq!((chip8.get_reg(1), chip8.get_reg(0xF)), (0x40, 1));
+    assert_eq!(chip8.get_pc(), 0x210);
+
+    let vip = run(Platform::CosmacVip);
+    assert_eq!((vip.get_reg(1), vip.get_reg(0xF)), (0x01, 0));
+
+    // B210 on SUPER-CHIP adds V2
+    let schip = run(Platform::SuperChip);
+    assert_eq!(schip.get_pc(), 0x212);
+}
+
+#[test]
+fn test_schip_draw() {
+    // HIGH; LD I, 0x300; DRW V0, V0, 0; DRW V0, V0, 0
+    let rom = [0x00, 0xFF, 0xA3, 0x00, 0xD0, 0x00, 0xD0, 0x00];
+    let mut chip8 = Chip8::with_platform(Platform::SuperChip);
+    chip8.load_to_ram(&rom).unwrap();
+    chip8.set_index_reg(0x300);
+    chip8.store_from_i(vec![0xFF; 32]).unwrap();
+    for _ in 0..3 {
+        chip8.cpu_loop().unwrap();
+    }
+    assert_eq!((chip8.display.num_rows, chip8.display.num_cols), (64, 128));
+    assert_eq!(chip8.display.get_display_buffer(15, 15), 1);
+    assert_eq!(chip8.get_reg(0xF), 0);
+    // drawing it again collides on all 16 rows
+    chip8.cpu_loop().unwrap();
+    assert_eq!(chip8.get_reg(0xF), 16);
+
+    // plain CHIP-8 doesn't know 00FF
+    let mut chip8 = Chip8::new();
+    chip8.load_to_ram(&rom).unwrap();
+    assert!(chip8.cpu_loop().is_err());
+}
+
+#[test]
+fn test_clipped_draw() {
+    // LD V1, y; LD F, V0; DRW V0, V1, 5: glyph 0 with 3 rows off the bottom
+    let run = |platform: Platform, hires: bool, y: u8| {
+        let mut chip8 = Chip8::with_platform(platform);
+        chip8
+            .load_to_ram(&[0x61, y, 0xF0, 0x29, 0xD0, 0x15])
+            .unwrap();
+        chip8.set_hires(hires);
+        for _ in 0..3 {
+            chip8.cpu_loop().unwrap();
         }
+        chip8.get_reg(0xF)
+    };
+    // clipping alone isn't a collision
+    assert_eq!(run(Platform::Chip8, false, 30), 0);
+    assert_eq!(run(Platform::CosmacVip, false, 30), 0);
+    assert_eq!(run(Platform::SuperChip, false, 30), 0);
+    // SUPER-CHIP hires counts the clipped rows
+    assert_eq!(run(Platform::SuperChip, true, 62), 3);
+}
 
-        // block until key input
-        0x0a => {
-            chip8.block_till_key(reg_num);
-        }
-
-        // font char
-        0x29 => {
-            // index_reg I is set to address of hex char in VX
-            println!(
-                "{:04x}: LOAD CHAR @ V{:01x} ({:01x})",
-                opcode, reg_num, reg_val
-            );
-            chip8.load_char_into_index_reg(reg_val);
-        }
-
-        // binary-coded decimal conversion
-        0x33 => {
-            // takes reg_val and puts each digit into memory starting at I
-            chip8.store_from_i(utils::extract_digits_u8(reg_val));
-        }
-
-        // store mem
-        0x55 => {
-            // get all register values
-
-            let vals = chip8.get_regs_in_range(reg_num);
-            println!(
-                "{:04x}: SAVE from {:03x}, VALS {:?} from REGS V0->V{:01x}",
-                opcode,
-                chip8.get_index_reg(),
-                vals,
-                reg_num
-            );
-            // let vals = regs.into_iter().map(|r_num| chip8.get_reg(r_num)).collect();
-            chip8.store_from_i(vals);
-        }
-        // load mem
-        0x65 => {
-            println!(
-                "{:04x}: LOAD from {:03x} INTO REGS V0->V{:01x}",
-                opcode,
-                chip8.get_index_reg(),
-                reg_num
-            );
-            chip8.load_from_i(reg_num)
-        }
-        _ => panic!("Error: Invalid instruction"),
+#[test]
+fn test_xochip() {
+    let rom = [
+        0xF0, 0x00, 0x12, 0x34, // LDL I, 0x1234
+        0x30, 0x00, // SE V0, 0x00
+        0xF0, 0x00, 0x00, 0x00, // LDL I, 0x0000 (skipped)
+        0x60, 0x81, // LD V0, 0x81
+        0x61, 0x18, // LD V1, 0x18
+        0x51, 0x02, // SAVE V1, V0
+        0xF3, 0x01, // PLANE 3
+        0x62, 0x00, // LD V2, 0x00
+        0xD2, 0x21, // DRW V2, V2, 1
+    ];
+    let mut chip8 = Chip8::with_platform(Platform::XoChip);
+    chip8.load_to_ram(&rom).unwrap();
+    for _ in 0..8 {
+        chip8.cpu_loop().unwrap();
     }
+    assert_eq!(chip8.get_index_reg(), 0x1234);
+    assert_eq!(chip8.get_mem_data(0x1234).unwrap(), 0x18);
+    assert_eq!(chip8.get_mem_data(0x1235).unwrap(), 0x81);
+    // plane 1 gets 0x18, plane 2 the byte after it
+    let row = &chip8.display.buffer()[0];
+    assert_eq!(&row[..8], &[2, 0, 0, 1, 1, 0, 0, 2]);
+}
+
+#[test]
+fn test_long_load_elsewhere() {
+    // F000 at the end of memory has no operand to fetch, but off XO-CHIP it's
+    // just an invalid 2 byte opcode
+    let mut chip8 = Chip8::with_platform(Platform::Chip8);
+    chip8.set_fault_policy(crate::error::FaultPolicy::Skip);
+    chip8.set_mem_data(0xFFE, 0xF0).unwrap();
+    chip8.set_pc(0xFFE);
+    assert_eq!(
+        chip8.cpu_loop(),
+        Err(Chip8Error::InvalidOpcode {
+            opcode: 0xF000,
+            addr: 0xFFE
+        })
+    );
+    assert_eq!(chip8.get_pc(), 0x1000);
 }
@@ -1,42 +1,211 @@
-use sdl2::keyboard::Keycode;
+use std::time::{Duration, Instant};
 
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+// how long a key stays down after a press with no key up to follow, long
+// enough to cover the usual delay before auto-repeat starts
+pub const DEFAULT_KEY_TIMEOUT: Duration = Duration::from_millis(500);
+// once repeats are coming in, they arrive every ~30-50 ms
+pub const KEY_REPEAT_TIMEOUT: Duration = Duration::from_millis(100);
+
+// a change on the hex keypad (0x0 - 0xF), mapping host keys onto the keypad
+// is up to the frontend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEvent {
+    Down(u8),
+    Up(u8),
+}
+
+// state of all 16 keys, one bit per key. Frontends queue events as they
+// arrive and they are applied before the next instruction runs, so nothing
+// pressed between two `cpu_loop` calls is lost. Presses are also latched for
+// a frame so a tap shorter than a frame is still seen by EX9E/EXA1/FX0A.
 pub struct KeyInput {
-    curr_pressed_key: Option<Keycode>,
+    events: Vec<KeyEvent>,
+    held: u16,
+    // pressed during the current frame
+    fresh: u16,
+    // pressed during the previous frame
+    latched: u16,
+    // released since the last FX0A looked
+    released: u16,
+}
+
+fn key_bit(key: u8) -> u16 {
+    1 << (key & 0xF)
 }
 
 impl KeyInput {
     pub fn new() -> KeyInput {
         KeyInput {
-            curr_pressed_key: None,
+            events: Vec::new(),
+            held: 0,
+            fresh: 0,
+            latched: 0,
+            released: 0,
         }
     }
 
-    pub fn update_curr_pressed_key(&mut self, kc: Option<Keycode>) {
-        self.curr_pressed_key = kc;
+    pub fn push_event(&mut self, event: KeyEvent) {
+        self.events.push(event);
     }
-    pub fn get_curr_pressed_key(&self) -> Option<Keycode> {
-        self.curr_pressed_key
+
+    pub fn pending_events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    // apply every queued event in arrival order
+    pub fn process_events(&mut self) {
+        for event in self.events.drain(..) {
+            match event {
+                KeyEvent::Down(key) => {
+                    self.held |= key_bit(key);
+                    self.fresh |= key_bit(key);
+                }
+                KeyEvent::Up(key) => {
+                    self.held &= !key_bit(key);
+                    self.released |= key_bit(key);
+                }
+            }
+        }
+    }
+
+    // called once per 60 Hz frame to age the tap latches
+    pub fn end_frame(&mut self) {
+        self.latched = self.fresh;
+        self.fresh = 0;
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.get_state() & key_bit(key) != 0
+    }
+
+    // bit N set = key N is down (or was tapped within the last frame)
+    pub fn get_state(&self) -> u16 {
+        self.held | self.fresh | self.latched
+    }
+
+    // keys that are physically held right now, without the tap latches
+    pub fn get_held(&self) -> u16 {
+        self.held
+    }
+
+    pub fn clear_released(&mut self) {
+        self.released = 0;
+    }
+
+    // lowest key released since the last call to `clear_released`
+    pub fn take_released(&mut self) -> Option<u8> {
+        if self.released == 0 {
+            return None;
+        }
+        let key = self.released.trailing_zeros() as u8;
+        self.released &= !key_bit(key);
+        Some(key)
+    }
+
+    // queued events haven't reached the machine yet and aren't saved
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        for mask in [self.held, self.fresh, self.latched, self.released] {
+            w.u16(mask);
+        }
+    }
+
+    pub(crate) fn read_state(r: &mut StateReader) -> Result<KeyInput, StateError> {
+        Ok(KeyInput {
+            events: Vec::new(),
+            held: r.u16()?,
+            fresh: r.u16()?,
+            latched: r.u16()?,
+            released: r.u16()?,
+        })
     }
 }
 
-pub fn chip8_keycode_map(kc: Keycode) -> Option<u8> {
-    match kc {
-        Keycode::Num0 => Some(0x0),
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0x4),
-        Keycode::Num5 => Some(0x5),
-        Keycode::Num6 => Some(0x6),
-        Keycode::Num7 => Some(0x7),
-        Keycode::Num8 => Some(0x8),
-        Keycode::Num9 => Some(0x9),
-        Keycode::A => Some(0xa),
-        Keycode::B => Some(0xb),
-        Keycode::C => Some(0xc),
-        Keycode::D => Some(0xd),
-        Keycode::E => Some(0xe),
-        Keycode::F => Some(0xf),
-        _ => None,
+impl Default for KeyInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Turns press-only input (a terminal sends a key again on auto-repeat but
+// never says it was let go) into key downs and ups: a key is released once
+// no press or repeat came in for a while.
+pub struct KeyTimeouts {
+    first: Duration,
+    // when each held key times out
+    deadlines: [Option<Instant>; 16],
+}
+
+impl KeyTimeouts {
+    pub fn new(first: Duration) -> KeyTimeouts {
+        KeyTimeouts {
+            first,
+            deadlines: [None; 16],
+        }
+    }
+
+    // a press or a repeat, Down if the key wasn't already held
+    pub fn press(&mut self, key: u8, now: Instant) -> Option<KeyEvent> {
+        let deadline = &mut self.deadlines[key as usize & 0xF];
+        let held = deadline.is_some();
+        *deadline = Some(now + if held { KEY_REPEAT_TIMEOUT } else { self.first });
+        (!held).then_some(KeyEvent::Down(key))
+    }
+
+    // for hosts that do report a release
+    pub fn release(&mut self, key: u8) -> Option<KeyEvent> {
+        self.deadlines[key as usize & 0xF]
+            .take()
+            .map(|_| KeyEvent::Up(key))
     }
+
+    // Up for every key that timed out by now
+    pub fn expire(&mut self, now: Instant) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        for (key, deadline) in self.deadlines.iter_mut().enumerate() {
+            if deadline.is_some_and(|deadline| deadline <= now) {
+                *deadline = None;
+                events.push(KeyEvent::Up(key as u8));
+            }
+        }
+        events
+    }
+}
+
+#[test]
+fn test_simultaneous_keys_and_taps() {
+    let mut keys = KeyInput::new();
+    keys.push_event(KeyEvent::Down(0x1));
+    keys.push_event(KeyEvent::Down(0x2));
+    keys.push_event(KeyEvent::Up(0x1));
+    keys.push_event(KeyEvent::Down(0xa));
+    keys.push_event(KeyEvent::Up(0xa));
+    keys.process_events();
+    assert_eq!(keys.get_held(), 1 << 0x2);
+    // 0x1 and 0xA were tapped this frame and stay visible
+    assert!(keys.is_pressed(0x1) && keys.is_pressed(0x2) && keys.is_pressed(0xa));
+    keys.end_frame();
+    assert!(keys.is_pressed(0xa));
+    keys.end_frame();
+    assert!(!keys.is_pressed(0xa));
+    assert!(keys.is_pressed(0x2));
+    assert_eq!(keys.take_released(), Some(0x1));
+    assert_eq!(keys.take_released(), Some(0xa));
+    assert_eq!(keys.take_released(), None);
+}
+
+#[test]
+fn test_key_timeouts() {
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let mut keys = KeyTimeouts::new(DEFAULT_KEY_TIMEOUT);
+    assert_eq!(keys.press(0x5, at(0)), Some(KeyEvent::Down(0x5)));
+    // auto-repeat kicks in before the first timeout, then comes quickly
+    assert_eq!(keys.press(0x5, at(450)), None);
+    assert_eq!(keys.expire(at(500)), vec![]);
+    assert_eq!(keys.expire(at(550)), vec![KeyEvent::Up(0x5)]);
+    assert_eq!(keys.press(0xa, at(600)), Some(KeyEvent::Down(0xa)));
+    assert_eq!(keys.release(0xa), Some(KeyEvent::Up(0xa)));
+    assert_eq!(keys.release(0xa), None);
 }
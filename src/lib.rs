@@ -0,0 +1,43 @@
+//! Headless CHIP-8 interpreter core.
+//!
+//! Nothing in here depends on SDL: frontends feed key state into
+//! [`key_input::KeyInput`], drive the machine through a
+//! [`scheduler::Scheduler`], read the framebuffer back out through a
+//! [`video::VideoSink`] and play the buzzer through an [`audio::AudioSink`].
+
+pub mod asm;
+pub mod audio;
+pub mod capture;
+pub mod conformance;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod emu_timer;
+pub mod error;
+pub mod instr;
+pub mod key_input;
+pub mod movie;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod rom;
+pub mod savestate;
+pub mod scheduler;
+pub mod screenshot;
+pub mod trace;
+mod utils;
+pub mod video;
+pub mod watch;
+
+pub use cpu::Chip8;
+pub use emu_timer::EmuTimer;
+pub use error::{Chip8Error, FaultPolicy};
+pub use quirks::{Platform, Quirks};
+
+// defaults for NUM_ROWS and NUM_COLS in the display grid
+pub const NUM_ROWS: u8 = 32;
+pub const NUM_COLS: u8 = 64;
+// SUPER-CHIP high resolution mode
+pub const HIRES_ROWS: u8 = 64;
+pub const HIRES_COLS: u8 = 128;
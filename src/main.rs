@@ -1,80 +1,313 @@
-mod cpu;
-mod display;
-mod emu_timer;
-mod instr;
-mod key_input;
-mod rom;
-mod utils;
-
-use cpu::Chip8;
-use sdl2::event::Event;
+mod cli;
+#[cfg(feature = "sdl")]
+mod sdl_frontend;
+#[cfg(feature = "tui")]
+mod tui_frontend;
+
+use chip8_rs::asm;
+use chip8_rs::audio::WavRecorder;
+use chip8_rs::capture;
+use chip8_rs::conformance::{self, Golden};
+use chip8_rs::debugger::Debugger;
+use chip8_rs::disasm::{self, Syntax};
+use chip8_rs::movie::{Movie, MoviePlayer};
+use chip8_rs::scheduler::Scheduler;
+use chip8_rs::screenshot;
+use chip8_rs::{rom, Chip8, Platform};
+use cli::{Command, Options, TestRomOptions};
 use std::cell::RefCell;
 use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::rc::Rc;
-use std::time::Duration;
-
-// defaults for NUM_ROWS and NUM_COLS in the display grid
-pub const NUM_ROWS: u8 = 32;
-pub const NUM_COLS: u8 = 64;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let file_path = {
-        match args.len() {
-            1 => "./chip8-roms/programs/IBM Logo.ch8",
-            2 => &args[1],
-            _ => panic!("Error: Usage chip8-rs 'IBM Logo.ch8'"),
+    let command = match cli::parse_args(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(2);
         }
     };
 
-    let sdl_context = sdl2::init().unwrap();
-    let vid_subsystem = sdl_context.video().unwrap();
-
-    let window = vid_subsystem
-        .window(
-            "Chip8 Emulator",
-            NUM_COLS as u32 * 10,
-            NUM_ROWS as u32 * 10,
-        )
-        .position_centered()
-        .build()
-        .unwrap();
-
-    let canvas = window.into_canvas().build().unwrap();
-
-    // initialize the display
-    let mut display = display::Display::new(NUM_ROWS as usize, NUM_COLS as usize, canvas);
-    // TODO: is there a better way to associate key presses?
-    let key_input = Rc::new(RefCell::new(key_input::KeyInput::new()));
-
-    let mut cpu = Chip8::new(&mut display, Rc::clone(&key_input));
-    // load in rom file
-    let instrs = rom::read_rom(file_path.to_string());
-    cpu.load_to_ram(&instrs);
-
-    // -- DEBUG
-    cpu.inspect_ram();
-
-    let mut event_pump = sdl_context.event_pump().unwrap();
-
-    // TODO: technically this event loop can be done inside cpu
-    // TODO: this leads into broader idea of refactoring cpu?
-    'running: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } => break 'running,
-                // update key_input's currently pressed key
-                Event::KeyDown {
-                    keycode: Some(kc), ..
-                } => key_input.borrow_mut().update_curr_pressed_key(Some(kc)),
-                // reset key being pressed
-                Event::KeyUp { .. } => key_input.borrow_mut().update_curr_pressed_key(None),
-                _ => {}
+    let result = match command {
+        Command::Run(options) if options.debug => debug(&options),
+        Command::Run(options) if options.tui => tui(&options),
+        Command::Run(options) => run(&options),
+        Command::Disasm { rom_path, syntax } => disasm(&rom_path, syntax),
+        Command::Asm {
+            source_path,
+            out_path,
+        } => assemble(&source_path, &out_path),
+        Command::Wav {
+            options,
+            frames,
+            out_path,
+        } => render_wav(&options, frames, &out_path),
+        Command::Play {
+            movie_path,
+            options,
+        } => play(&movie_path, &options),
+        Command::TestRom { options, test } => test_rom(&options, &test),
+        Command::Video {
+            options,
+            frames,
+            input,
+            scale,
+            out_path,
+        } => record_video(&options, frames, input.as_deref(), scale, &out_path),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn run(options: &Options) -> Result<(), String> {
+    sdl_frontend::run(options);
+    Ok(())
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run(options: &Options) -> Result<(), String> {
+    Err(format!(
+        "Error: chip8-rs was built without the `sdl` feature, cannot open a window for {}",
+        options.rom_path
+    ))
+}
+
+#[cfg(feature = "tui")]
+fn tui(options: &Options) -> Result<(), String> {
+    tui_frontend::run(options)
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui(options: &Options) -> Result<(), String> {
+    Err(format!(
+        "Error: chip8-rs was built without the `tui` feature, cannot run {} in the terminal",
+        options.rom_path
+    ))
+}
+
+// headless REPL on stdin, see `debugger::HELP` for the commands
+fn debug(options: &Options) -> Result<(), String> {
+    let mut chip8 = options.build_chip8()?;
+    let mut debugger = Debugger::new(options.ips);
+    println!("{} loaded, `help` lists the commands", options.rom_path);
+    if let Ok(out) = debugger.command(&mut chip8, "disasm") {
+        println!("{}", out);
+    }
+    let stdin = io::stdin();
+    loop {
+        print!("(chip8) ");
+        io::stdout()
+            .flush()
+            .map_err(|err| format!("Error: {}", err))?;
+        let mut line = String::new();
+        let read = stdin
+            .read_line(&mut line)
+            .map_err(|err| format!("Error: {}", err))?;
+        if read == 0 {
+            // ctrl-d, end the prompt line
+            println!();
+            return Ok(());
+        }
+        if matches!(line.trim(), "quit" | "q") {
+            return Ok(());
+        }
+        match debugger.command(&mut chip8, &line) {
+            Ok(out) if out.is_empty() => {}
+            Ok(out) => println!("{}", out),
+            Err(err) => println!("Error: {}", err),
+        }
+    }
+}
+
+fn disasm(rom_path: &str, syntax: Syntax) -> Result<(), String> {
+    // same checks as loading it to run, with the biggest (XO-CHIP) memory
+    let instrs = rom::read_rom(rom_path.to_string())
+        .and_then(|instrs| {
+            Chip8::with_platform(Platform::XoChip)
+                .load_to_ram(&instrs)
+                .map(|_| instrs)
+        })
+        .map_err(|err| format!("Error: {}", err))?;
+    print!("{}", disasm::disassemble(&instrs, syntax));
+    Ok(())
+}
+
+fn assemble(source_path: &str, out_path: &str) -> Result<(), String> {
+    let source = std::fs::read_to_string(source_path)
+        .map_err(|err| format!("Error: {}: {}", source_path, err))?;
+    let rom = asm::assemble(&source).map_err(|err| format!("{}:{}", source_path, err))?;
+    std::fs::write(out_path, rom).map_err(|err| format!("Error: {}: {}", out_path, err))
+}
+
+// headless runs end at a break watchpoint
+fn stopped_by_watchpoint(chip8: &mut Chip8) -> bool {
+    match chip8.take_watch_hit() {
+        Some(hit) => {
+            eprintln!("Stopped by watchpoint: {}", hit);
+            true
+        }
+        None => false,
+    }
+}
+
+const WAV_SAMPLE_RATE: u32 = 44100;
+
+fn render_wav(options: &Options, frames: u32, out_path: &str) -> Result<(), String> {
+    let mut chip8 = options.build_chip8()?;
+    let recorder = Rc::new(RefCell::new(WavRecorder::new(
+        options.audio,
+        WAV_SAMPLE_RATE,
+    )));
+    chip8.set_audio_sink(Box::new(recorder.clone()));
+    let mut scheduler = Scheduler::new(options.ips);
+    // a halt ends the recording early, what played until then is kept
+    for _ in 0..frames {
+        if chip8.is_halted() || stopped_by_watchpoint(&mut chip8) {
+            break;
+        }
+        if let Err(err) = scheduler.run_frame(&mut chip8) {
+            eprintln!("Error: {}", err);
+        }
+    }
+    let file = File::create(out_path).map_err(|err| format!("Error: {}: {}", out_path, err))?;
+    let written = recorder.borrow().write_wav(BufWriter::new(file));
+    written.map_err(|err| format!("Error: {}: {}", out_path, err))
+}
+
+fn play(movie_path: &str, options: &Options) -> Result<(), String> {
+    let movie = cli::read_movie(movie_path)?;
+    let mut chip8 = options.build_movie_chip8(&movie)?;
+    let mut scheduler = Scheduler::new(movie.ips);
+    let mut player = MoviePlayer::new(movie);
+    while player.start_frame(&mut chip8) {
+        if chip8.is_halted() || stopped_by_watchpoint(&mut chip8) {
+            break;
+        }
+        if let Err(err) = scheduler.run_frame(&mut chip8) {
+            eprintln!("Error: {}", err);
+        }
+    }
+    println!(
+        "Played {} of {} frames",
+        player.get_frame(),
+        player.movie().frames.len()
+    );
+    print!("{}", chip8.display.render_grid());
+    Ok(())
+}
+
+// A seeded machine and a player feeding it the --input script (inline, or
+// @path to a file of it) for a fixed number of frames. Scripted input is
+// fed in like a movie, on frame boundaries.
+fn scripted_run(
+    options: &Options,
+    input: Option<&str>,
+    frames: u32,
+) -> Result<(Chip8, MoviePlayer), String> {
+    let rom = rom::read_rom(options.rom_path.clone()).map_err(|err| format!("Error: {}", err))?;
+    let mut chip8 = options.build_chip8()?;
+    // the same run every time unless seeded otherwise
+    if options.seed.is_none() {
+        chip8.seed_rng(0);
+    }
+    let script = match input {
+        Some(input) => match input.strip_prefix('@') {
+            Some(path) => {
+                std::fs::read_to_string(path).map_err(|err| format!("Error: {}: {}", path, err))?
             }
+            None => input.to_string(),
+        },
+        None => String::new(),
+    };
+    let mut movie = Movie::new(&rom, &chip8, options.ips);
+    movie.frames = conformance::parse_input_script(&script, frames)
+        .map_err(|err| format!("Error: --input: {}", err))?;
+    Ok((chip8, MoviePlayer::new(movie)))
+}
+
+// Run a ROM headless for a fixed number of frames with scripted input, then
+// check the screen against a golden file (or write it with --update). A
+// mismatch prints the diff and fails the command.
+fn test_rom(options: &Options, test: &TestRomOptions) -> Result<(), String> {
+    let (mut chip8, mut player) = scripted_run(options, test.input.as_deref(), test.frames)?;
+    let mut scheduler = Scheduler::new(options.ips);
+    while player.start_frame(&mut chip8) {
+        if chip8.is_halted() || stopped_by_watchpoint(&mut chip8) {
+            break;
+        }
+        if let Err(err) = scheduler.run_frame(&mut chip8) {
+            eprintln!("Error: {}", err);
+        }
+    }
+
+    let screen = &chip8.display;
+    println!("framebuffer {:016x}", screen.hash());
+    if test.dump {
+        print!("{}", screen.render_grid());
+    }
+    if let Some(path) = &test.screenshot {
+        screenshot::save(screen, &options.palette, test.scale, Path::new(path))
+            .map_err(|err| format!("Error: {}: {}", path, err))?;
+    }
+    let Some(golden_path) = &test.golden else {
+        return Ok(());
+    };
+    if test.update {
+        let golden = Golden::from_framebuffer(screen, test.hash);
+        std::fs::write(golden_path, golden.to_string())
+            .map_err(|err| format!("Error: {}: {}", golden_path, err))?;
+        println!("Wrote {}", golden_path);
+        return Ok(());
+    }
+    let text = std::fs::read_to_string(golden_path)
+        .map_err(|err| format!("Error: {}: {}", golden_path, err))?;
+    match Golden::parse(&text).check(screen) {
+        Ok(()) => {
+            println!("{}: ok", options.rom_path);
+            Ok(())
         }
-        // canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        Err(diff) => {
+            print!("{}", diff);
+            Err(format!(
+                "Error: {} doesn't match {}",
+                options.rom_path, golden_path
+            ))
+        }
+    }
+}
 
-        cpu.cpu_loop();
+// Run a ROM headless with scripted input, recording every frame as a GIF or
+// a Y4M stream (`-` for stdout).
+fn record_video(
+    options: &Options,
+    frames: u32,
+    input: Option<&str>,
+    scale: u32,
+    out_path: &str,
+) -> Result<(), String> {
+    let (mut chip8, mut player) = scripted_run(options, input, frames)?;
+    let mut recording = capture::open(out_path, &options.palette, scale)
+        .map_err(|err| format!("Error: {}: {}", out_path, err))?;
+    let mut scheduler = Scheduler::new(options.ips);
+    // a halt ends the recording early, like for wav
+    while player.start_frame(&mut chip8) {
+        if chip8.is_halted() || stopped_by_watchpoint(&mut chip8) {
+            break;
+        }
+        if let Err(err) = scheduler.run_frame(&mut chip8) {
+            eprintln!("Error: {}", err);
+        }
+        recording.present(&chip8.display);
     }
+    recording
+        .close()
+        .map_err(|err| format!("Error: {}: {}", out_path, err))
 }
@@ -0,0 +1,334 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::cpu::Chip8;
+use crate::error::Chip8Error;
+use crate::key_input::KeyEvent;
+use crate::quirks::{Platform, Quirks, PLATFORMS};
+use crate::rng::RngKind;
+use crate::rom::rom_hash;
+
+// Movie file layout (version 2), all numbers little endian:
+//
+//   magic        4 bytes  "C8MV"
+//   version      u16      2
+//   rom_hash     u64      FNV-1a of the ROM file (`rom::rom_hash`)
+//   rng_seed     u64      seed CXNN was started from
+//   rng          u8       random generator, see `rng::RngKind`
+//   ips          u32      instructions per second
+//   platform     u8       index into `quirks::PLATFORMS`
+//   quirks       u8       bit N = quirk `quirks::QUIRK_NAMES[N]` on
+//   frame_count  u32
+//   frames       u16 * frame_count, bit N = key N is down during that frame
+//
+// Input is only ever applied at the start of a frame, while recording as
+// well as during playback, so a replay runs exactly like the recording did.
+// Bump MOVIE_VERSION whenever the layout changes.
+pub const MOVIE_MAGIC: &[u8; 4] = b"C8MV";
+pub const MOVIE_VERSION: u16 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MovieError {
+    // doesn't start with the magic bytes
+    NotAMovie,
+    // written by another version of the emulator
+    UnsupportedVersion { version: u16 },
+    Truncated,
+    Invalid(String),
+    // recorded with a different ROM
+    RomMismatch { expected: u64, found: u64 },
+    // the ROM doesn't load
+    Load(Chip8Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "not a chip8-rs movie"),
+            MovieError::UnsupportedVersion { version } => write!(
+                f,
+                "movie version {} is not supported, this build reads version {}",
+                version, MOVIE_VERSION
+            ),
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::Invalid(reason) => write!(f, "corrupt movie: {}", reason),
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "movie was recorded with ROM {:016x}, this ROM is {:016x}",
+                expected, found
+            ),
+            MovieError::Load(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub rng_seed: u64,
+    pub rng: RngKind,
+    pub ips: u32,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    // keypad state per frame
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    // an empty movie starting from the machine as it is now
+    pub fn new(rom: &[u8], chip8: &Chip8, ips: u32) -> Movie {
+        Movie {
+            rom_hash: rom_hash(rom),
+            rng_seed: chip8.get_rng_seed(),
+            rng: chip8.get_rng_kind(),
+            ips,
+            platform: chip8.get_platform(),
+            quirks: chip8.get_quirks(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(MOVIE_MAGIC)?;
+        out.write_all(&MOVIE_VERSION.to_le_bytes())?;
+        out.write_all(&self.rom_hash.to_le_bytes())?;
+        out.write_all(&self.rng_seed.to_le_bytes())?;
+        out.write_all(&[self.rng.to_u8()])?;
+        out.write_all(&self.ips.to_le_bytes())?;
+        let platform = PLATFORMS.iter().position(|p| *p == self.platform).unwrap();
+        out.write_all(&[platform as u8, self.quirks.to_bits()])?;
+        out.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        for keys in &self.frames {
+            out.write_all(&keys.to_le_bytes())?;
+        }
+        out.flush()
+    }
+
+    pub fn read(data: &[u8]) -> Result<Movie, MovieError> {
+        if !data.starts_with(MOVIE_MAGIC) {
+            return Err(MovieError::NotAMovie);
+        }
+        let mut pos = MOVIE_MAGIC.len();
+        let mut take = |len: usize| {
+            let bytes = data.get(pos..pos + len).ok_or(MovieError::Truncated);
+            pos += len;
+            bytes
+        };
+        let version = u16::from_le_bytes(take(2)?.try_into().unwrap());
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion { version });
+        }
+        let rom_hash = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let rng_seed = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let rng = take(1)?[0];
+        let rng = RngKind::from_u8(rng).ok_or(MovieError::Invalid(format!(
+            "unknown random generator {}",
+            rng
+        )))?;
+        let ips = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let platform = take(1)?[0];
+        let platform = *PLATFORMS
+            .get(platform as usize)
+            .ok_or(MovieError::Invalid(format!(
+                "unknown platform {}",
+                platform
+            )))?;
+        let quirks = Quirks::from_bits(take(1)?[0]);
+        let frame_count = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let frames = take(frame_count * 2)?
+            .chunks(2)
+            .map(|keys| u16::from_le_bytes([keys[0], keys[1]]))
+            .collect();
+        if pos != data.len() {
+            return Err(MovieError::Invalid(format!(
+                "{} unexpected trailing bytes",
+                data.len() - pos
+            )));
+        }
+        Ok(Movie {
+            rom_hash,
+            rng_seed,
+            rng,
+            ips,
+            platform,
+            quirks,
+            frames,
+        })
+    }
+
+    pub fn verify_rom(&self, rom: &[u8]) -> Result<(), MovieError> {
+        let found = rom_hash(rom);
+        if found != self.rom_hash {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_hash,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    // a machine in the same state the recording started from
+    pub fn build_chip8(&self, rom: &[u8]) -> Result<Chip8, MovieError> {
+        self.verify_rom(rom)?;
+        let mut chip8 = Chip8::with_platform(self.platform);
+        chip8.set_quirks(self.quirks);
+        // recorded with a generator that only the recording program knows
+        let rng = self.rng.build(self.rng_seed).ok_or(MovieError::Invalid(
+            "recorded with a custom random generator".to_string(),
+        ))?;
+        chip8.set_rng(rng);
+        chip8.seed_rng(self.rng_seed);
+        chip8.load_to_ram(rom).map_err(MovieError::Load)?;
+        Ok(chip8)
+    }
+}
+
+// queue the changes between two keypad states
+fn feed_keys(chip8: &mut Chip8, prev: u16, next: u16) {
+    for key in 0..16 {
+        let bit = 1 << key;
+        if next & bit != 0 && prev & bit == 0 {
+            chip8.key_input.push_event(KeyEvent::Down(key));
+        } else if next & bit == 0 && prev & bit != 0 {
+            chip8.key_input.push_event(KeyEvent::Up(key));
+        }
+    }
+}
+
+// Takes the live keypad events in place of `KeyInput` and hands them to the
+// machine one frame at a time. A key tapped and let go within a frame counts
+// as down for that whole frame.
+pub struct MovieRecorder {
+    movie: Movie,
+    held: u16,
+    tapped: u16,
+    // what the machine has been given so far
+    fed: u16,
+}
+
+impl MovieRecorder {
+    pub fn new(movie: Movie) -> MovieRecorder {
+        MovieRecorder {
+            movie,
+            held: 0,
+            tapped: 0,
+            fed: 0,
+        }
+    }
+
+    pub fn key_event(&mut self, event: KeyEvent) {
+        match event {
+            KeyEvent::Down(key) => {
+                self.held |= 1 << (key & 0xF);
+                self.tapped |= 1 << (key & 0xF);
+            }
+            KeyEvent::Up(key) => self.held &= !(1 << (key & 0xF)),
+        }
+    }
+
+    // call at the start of every frame
+    pub fn start_frame(&mut self, chip8: &mut Chip8) {
+        let keys = self.held | self.tapped;
+        self.tapped = 0;
+        feed_keys(chip8, self.fed, keys);
+        self.fed = keys;
+        self.movie.frames.push(keys);
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+    fed: u16,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> MoviePlayer {
+        MoviePlayer {
+            movie,
+            frame: 0,
+            fed: 0,
+        }
+    }
+
+    // call at the start of every frame, returns false once the movie is over
+    // (and lets go of every key)
+    pub fn start_frame(&mut self, chip8: &mut Chip8) -> bool {
+        let keys = self.movie.frames.get(self.frame).copied().unwrap_or(0);
+        feed_keys(chip8, self.fed, keys);
+        self.fed = keys;
+        if self.is_finished() {
+            return false;
+        }
+        self.frame += 1;
+        true
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    pub fn get_frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+#[test]
+fn test_record_and_play() {
+    use crate::scheduler::Scheduler;
+
+    // wait for a key, draw its digit at a random x, loop
+    let rom = [
+        0xF0, 0x0A, // LD V0, K
+        0xF0, 0x29, // LD F, V0
+        0xC1, 0x3F, // RND V1, 0x3f
+        0xD1, 0x25, // DRW V1, V2, 5
+        0x12, 0x00, // JP 0x200
+    ];
+    let mut chip8 = Chip8::new();
+    chip8.load_to_ram(&rom).unwrap();
+    let mut recorder = MovieRecorder::new(Movie::new(&rom, &chip8, 600));
+    let mut scheduler = Scheduler::new(600);
+    for frame in 0..10 {
+        match frame {
+            2 => recorder.key_event(KeyEvent::Down(5)),
+            3 => recorder.key_event(KeyEvent::Up(5)),
+            // tapped and let go within the same frame
+            5 => {
+                recorder.key_event(KeyEvent::Down(9));
+                recorder.key_event(KeyEvent::Up(9));
+            }
+            _ => {}
+        }
+        recorder.start_frame(&mut chip8);
+        scheduler.run_frame(&mut chip8).unwrap();
+    }
+    let mut file = Vec::new();
+    recorder.movie().write(&mut file).unwrap();
+
+    let movie = Movie::read(&file).unwrap();
+    assert_eq!(movie.frames.len(), 10);
+    assert_eq!(movie.frames[5], 1 << 9);
+    assert!(matches!(
+        movie.build_chip8(&[0x00, 0xE0]),
+        Err(MovieError::RomMismatch { .. })
+    ));
+    let mut replay = movie.build_chip8(&rom).unwrap();
+    let mut player = MoviePlayer::new(movie);
+    let mut scheduler = Scheduler::new(600);
+    while player.start_frame(&mut replay) {
+        scheduler.run_frame(&mut replay).unwrap();
+    }
+    assert_eq!(replay.display.buffer(), chip8.display.buffer());
+    assert_eq!(replay.get_reg(0), 9);
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::str::FromStr;
+
+// The opcodes interpreters never agreed on. Every flag picks one of the two
+// behaviors seen in the wild.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY into VX (COSMAC VIP) instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    // FX55/FX65 leave I pointing past the last register (COSMAC VIP, XO-CHIP)
+    pub load_store_increments_i: bool,
+    // BNNN is really BXNN and jumps to XNN + VX (CHIP-48, SUPER-CHIP)
+    pub jump_with_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0 (COSMAC VIP)
+    pub vf_reset: bool,
+    // sprites are cut off at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    // DXYN waits for the next 60 Hz frame before the program continues
+    // (COSMAC VIP), so at most one sprite is drawn per frame
+    pub display_wait: bool,
+}
+
+pub const QUIRK_NAMES: [&str; 6] = [
+    "shift",
+    "memory",
+    "jump",
+    "vf-reset",
+    "clip",
+    "display-wait",
+];
+
+impl Quirks {
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift" => Some(&mut self.shift_uses_vy),
+            "memory" => Some(&mut self.load_store_increments_i),
+            "jump" => Some(&mut self.jump_with_vx),
+            "vf-reset" => Some(&mut self.vf_reset),
+            "clip" => Some(&mut self.clip_sprites),
+            "display-wait" => Some(&mut self.display_wait),
+            _ => None,
+        }
+    }
+
+    // override single quirks with a list like "clip=off,shift=on"
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        for part in spec.split(',').filter(|part| !part.is_empty()) {
+            let (name, val) = part
+                .split_once('=')
+                .ok_or(format!("expected <quirk>=on|off, got {}", part))?;
+            let val = match val {
+                "on" | "true" | "1" => true,
+                "off" | "false" | "0" => false,
+                _ => return Err(format!("expected on or off for {}, got {}", name, val)),
+            };
+            let flag = self.flag_mut(name).ok_or(format!(
+                "unknown quirk {} ({})",
+                name,
+                QUIRK_NAMES.join(", ")
+            ))?;
+            *flag = val;
+        }
+        Ok(())
+    }
+
+    // bit N = quirk QUIRK_NAMES[N] is on, for save states and movies
+    pub fn to_bits(&self) -> u8 {
+        let mut quirks = *self;
+        QUIRK_NAMES.iter().enumerate().fold(0, |bits, (i, name)| {
+            bits | (*quirks.flag_mut(name).unwrap() as u8) << i
+        })
+    }
+
+    pub fn from_bits(bits: u8) -> Quirks {
+        let mut quirks = Quirks::default();
+        for (i, name) in QUIRK_NAMES.iter().enumerate() {
+            *quirks.flag_mut(name).unwrap() = bits >> i & 1 == 1;
+        }
+        quirks
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Platform::default().quirks()
+    }
+}
+
+// the interpreters quirk presets are modelled on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    // plain CHIP-8 the way most modern interpreters run it
+    #[default]
+    Chip8,
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+pub const PLATFORMS: [Platform; 5] = [
+    Platform::Chip8,
+    Platform::CosmacVip,
+    Platform::Chip48,
+    Platform::SuperChip,
+    Platform::XoChip,
+];
+
+impl Platform {
+    pub fn quirks(&self) -> Quirks {
+        let modern = Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_with_vx: false,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+        };
+        match self {
+            Platform::Chip8 => modern,
+            Platform::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                vf_reset: true,
+                display_wait: true,
+                ..modern
+            },
+            Platform::Chip48 | Platform::SuperChip => Quirks {
+                jump_with_vx: true,
+                ..modern
+            },
+            Platform::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                clip_sprites: false,
+                ..modern
+            },
+        }
+    }
+
+    // bytes of addressable memory
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    // hires, scrolling, big font and the RPL flags
+    pub fn has_schip_instrs(&self) -> bool {
+        matches!(self, Platform::SuperChip | Platform::XoChip)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::CosmacVip => "vip",
+            Platform::Chip48 => "chip48",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PLATFORMS
+            .into_iter()
+            .find(|platform| platform.name() == s)
+            .ok_or(format!(
+                "unknown platform {} ({})",
+                s,
+                PLATFORMS.map(|p| p.name()).join(", ")
+            ))
+    }
+}
+
+#[test]
+fn test_quirk_overrides() {
+    let mut quirks = Platform::CosmacVip.quirks();
+    quirks.apply("clip=off,jump=on").unwrap();
+    assert!(!quirks.clip_sprites && quirks.jump_with_vx && quirks.shift_uses_vy);
+    assert!(quirks.apply("warp=on").is_err());
+    let xochip = Platform::XoChip.quirks();
+    assert!(xochip.shift_uses_vy && xochip.load_store_increments_i && !xochip.clip_sprites);
+    assert_eq!("schip".parse(), Ok(Platform::SuperChip));
+}
@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+
+use crate::cpu::Chip8;
+
+// 10 seconds at 60 Hz
+pub const DEFAULT_REWIND_FRAMES: usize = 600;
+pub const DEFAULT_REWIND_BYTES: usize = 16 * 1024 * 1024;
+
+// turns a frame's save state back into the one before it
+enum Delta {
+    // (offset, old bytes) for every run of bytes that changed
+    Runs(Vec<(u32, Vec<u8>)>),
+    // the state changed size (resolution or platform switch), keep all of it
+    Full(Vec<u8>),
+}
+
+impl Delta {
+    // how to get from `newer` back to `older`
+    fn between(older: &[u8], newer: &[u8]) -> Delta {
+        if older.len() != newer.len() {
+            return Delta::Full(older.to_vec());
+        }
+        let mut runs: Vec<(u32, Vec<u8>)> = Vec::new();
+        let mut last_changed = None;
+        for (i, (old, new)) in older.iter().zip(newer).enumerate() {
+            if old == new {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((_, bytes)) if last_changed == Some(i - 1) => bytes.push(*old),
+                _ => runs.push((i as u32, vec![*old])),
+            }
+            last_changed = Some(i);
+        }
+        Delta::Runs(runs)
+    }
+
+    fn apply(&self, state: &mut Vec<u8>) {
+        match self {
+            Delta::Runs(runs) => {
+                for (offset, bytes) in runs {
+                    let offset = *offset as usize;
+                    state[offset..offset + bytes.len()].copy_from_slice(bytes);
+                }
+            }
+            Delta::Full(older) => state.clone_from(older),
+        }
+    }
+
+    // rough heap usage, to stay within the memory budget
+    fn size(&self) -> usize {
+        match self {
+            Delta::Runs(runs) => runs.iter().map(|(_, bytes)| 8 + bytes.len()).sum(),
+            Delta::Full(older) => older.len(),
+        }
+    }
+}
+
+// Ring buffer of the last frames, for running time backwards. Only the newest
+// frame is kept whole (as a save state); every older one is a delta against
+// the frame after it, so a frame that only touched a few registers, RAM bytes
+// and pixels costs a few bytes. The oldest frames are dropped once there are
+// more than `max_frames` or they take up more than `max_bytes`.
+pub struct Rewind {
+    max_frames: usize,
+    max_bytes: usize,
+    head: Option<Vec<u8>>,
+    // newest at the back
+    undo: VecDeque<Delta>,
+    undo_bytes: usize,
+}
+
+impl Rewind {
+    pub fn new(max_frames: usize, max_bytes: usize) -> Rewind {
+        Rewind {
+            max_frames,
+            max_bytes,
+            head: None,
+            undo: VecDeque::new(),
+            undo_bytes: 0,
+        }
+    }
+
+    // record the machine as it is at the end of a frame
+    pub fn push(&mut self, chip8: &Chip8) {
+        if self.max_frames == 0 {
+            return;
+        }
+        let state = chip8.save_state();
+        if let Some(head) = self.head.take() {
+            let delta = Delta::between(&head, &state);
+            self.undo_bytes += delta.size();
+            self.undo.push_back(delta);
+        }
+        self.head = Some(state);
+        while self.undo.len() >= self.max_frames || self.memory_used() > self.max_bytes {
+            match self.undo.pop_front() {
+                Some(delta) => self.undo_bytes -= delta.size(),
+                None => break,
+            }
+        }
+    }
+
+    // Go back one frame. The keypad stays as it is, so whatever the player
+    // holds when they let go of rewind is what the game sees. Returns false
+    // once the oldest frame has been reached.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> bool {
+        let Some(head) = &mut self.head else {
+            return false;
+        };
+        let stepped = match self.undo.pop_back() {
+            Some(delta) => {
+                self.undo_bytes -= delta.size();
+                delta.apply(head);
+                true
+            }
+            None => false,
+        };
+        let key_input = std::mem::take(&mut chip8.key_input);
+        // only ever holds states this build wrote
+        chip8.load_state(head).unwrap();
+        chip8.key_input = key_input;
+        stepped
+    }
+
+    // frames that can be stepped back through
+    pub fn len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty()
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.undo_bytes + self.head.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn clear(&mut self) {
+        self.head = None;
+        self.undo.clear();
+        self.undo_bytes = 0;
+    }
+}
+
+#[test]
+fn test_rewind() {
+    // ADD V0, 1; JP 0x200
+    let rom = [0x70, 0x01, 0x12, 0x00];
+    let mut chip8 = Chip8::new();
+    chip8.load_to_ram(&rom).unwrap();
+    let mut rewind = Rewind::new(4, DEFAULT_REWIND_BYTES);
+    for _ in 0..6 {
+        chip8.cpu_loop().unwrap();
+        chip8.cpu_loop().unwrap();
+        rewind.push(&chip8);
+    }
+    assert_eq!(chip8.get_reg(0), 6);
+    // only the last 4 frames are kept
+    assert_eq!(rewind.len(), 3);
+    assert!(rewind.step_back(&mut chip8));
+    assert_eq!(chip8.get_reg(0), 5);
+    assert!(rewind.step_back(&mut chip8));
+    assert!(rewind.step_back(&mut chip8));
+    assert_eq!(chip8.get_reg(0), 3);
+    assert!(!rewind.step_back(&mut chip8));
+    assert_eq!(chip8.get_reg(0), 3);
+
+    // running on from there records over the rewound frames
+    chip8.cpu_loop().unwrap();
+    chip8.cpu_loop().unwrap();
+    rewind.push(&chip8);
+    assert_eq!(rewind.len(), 1);
+    assert!(rewind.step_back(&mut chip8));
+    assert_eq!(chip8.get_reg(0), 3);
+}
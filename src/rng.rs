@@ -0,0 +1,252 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Where CXNN gets its random bytes from. The machine owns one (see
+// `Chip8::set_rng`), so runs can be seeded and replayed exactly.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    // called on every 60 Hz tick
+    fn tick(&mut self) {}
+
+    fn seed(&mut self, seed: u64);
+
+    // identifies the generator in save states and movies
+    fn kind(&self) -> RngKind;
+
+    // internal state, for save states
+    fn save(&self) -> Vec<u8>;
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), String>;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RngKind {
+    #[default]
+    Xorshift,
+    CosmacVip,
+    // anything handed to `Chip8::set_rng` from outside, can't be recreated
+    Custom,
+}
+
+pub const RNG_KINDS: [RngKind; 2] = [RngKind::Xorshift, RngKind::CosmacVip];
+
+impl RngKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RngKind::Xorshift => "xorshift",
+            RngKind::CosmacVip => "vip",
+            RngKind::Custom => "custom",
+        }
+    }
+
+    // a fresh generator of this kind, None for custom ones
+    pub fn build(&self, seed: u64) -> Option<Box<dyn RandomSource>> {
+        let mut rng: Box<dyn RandomSource> = match self {
+            RngKind::Xorshift => Box::new(Xorshift::new()),
+            RngKind::CosmacVip => Box::new(CosmacVip::new()),
+            RngKind::Custom => return None,
+        };
+        rng.seed(seed);
+        Some(rng)
+    }
+
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            RngKind::Xorshift => 0,
+            RngKind::CosmacVip => 1,
+            RngKind::Custom => 0xFF,
+        }
+    }
+
+    pub(crate) fn from_u8(val: u8) -> Option<RngKind> {
+        match val {
+            0 => Some(RngKind::Xorshift),
+            1 => Some(RngKind::CosmacVip),
+            0xFF => Some(RngKind::Custom),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RngKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for RngKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RNG_KINDS
+            .iter()
+            .find(|kind| kind.name() == s)
+            .copied()
+            .ok_or(format!("unknown random generator {} (xorshift, vip)", s))
+    }
+}
+
+fn restore_bytes<const N: usize>(state: &[u8]) -> Result<[u8; N], String> {
+    state
+        .try_into()
+        .map_err(|_| format!("expected {} bytes of generator state", N))
+}
+
+// xorshift64*, the default: fast, tiny state and the same sequence on every
+// build, which matters for movies
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    pub fn new() -> Xorshift {
+        let mut rng = Xorshift { state: 0 };
+        rng.seed(0);
+        rng
+    }
+}
+
+impl Default for Xorshift {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RandomSource for Xorshift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+
+    fn seed(&mut self, seed: u64) {
+        // splitmix64 so similar seeds start far apart, and never at 0
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        self.state = (z ^ (z >> 31)).max(1);
+    }
+
+    fn kind(&self) -> RngKind {
+        RngKind::Xorshift
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), String> {
+        self.state = u64::from_le_bytes(restore_bytes(state)?);
+        Ok(())
+    }
+}
+
+// every byte value once, in a fixed shuffled order
+const VIP_PAGE: [u8; 256] = {
+    let mut page = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        page[i] = i as u8;
+        i += 1;
+    }
+    // Fisher-Yates driven by a 32-bit LCG
+    let mut state: u32 = 0x1802;
+    let mut i = 255;
+    while i > 0 {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        let j = (state >> 16) as usize % (i + 1);
+        let swap = page[i];
+        page[i] = page[j];
+        page[j] = swap;
+        i -= 1;
+    }
+    page
+};
+
+// A counter generator in the style of the COSMAC VIP's CXNN routine: R9 is
+// stepped by the 60 Hz interrupt and once per call, and the table byte its
+// low half picks is added into its high half, which is the result. So the
+// numbers depend on when CXNN runs, but the table is our own, not the VIP
+// interpreter's, and the sequence isn't the one a real VIP produces.
+pub struct CosmacVip {
+    r9: u16,
+}
+
+impl CosmacVip {
+    pub fn new() -> CosmacVip {
+        CosmacVip { r9: 0 }
+    }
+}
+
+impl Default for CosmacVip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RandomSource for CosmacVip {
+    fn next_byte(&mut self) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+        let page_byte = VIP_PAGE[(self.r9 & 0xFF) as usize];
+        let high = ((self.r9 >> 8) as u8).wrapping_add(page_byte);
+        self.r9 = (high as u16) << 8 | self.r9 & 0xFF;
+        high
+    }
+
+    fn tick(&mut self) {
+        let low = (self.r9 as u8).wrapping_add(1);
+        self.r9 = self.r9 & 0xFF00 | low as u16;
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.r9 = seed as u16;
+    }
+
+    fn kind(&self) -> RngKind {
+        RngKind::CosmacVip
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.r9.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), String> {
+        self.r9 = u16::from_le_bytes(restore_bytes(state)?);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_seeded_generators() {
+    for kind in RNG_KINDS {
+        let mut a = kind.build(42).unwrap();
+        let mut b = kind.build(42).unwrap();
+        let first: Vec<u8> = (0..16).map(|_| a.next_byte()).collect();
+        let second: Vec<u8> = (0..16).map(|_| b.next_byte()).collect();
+        assert_eq!(first, second, "{}", kind);
+
+        // picks up exactly where the saved one left off
+        let saved = a.save();
+        let expected = a.next_byte();
+        let mut c = kind.build(7).unwrap();
+        c.restore(&saved).unwrap();
+        assert_eq!(c.next_byte(), expected);
+    }
+    // r9 = 0x0100, +1 = 0x0101, high = 1 + page[1]
+    let mut vip = CosmacVip::new();
+    vip.seed(0x0100);
+    assert_eq!(vip.next_byte(), VIP_PAGE[1].wrapping_add(1));
+    // doesn't get stuck repeating a byte
+    for seed in [0, 0x1234] {
+        vip.seed(seed);
+        let bytes: Vec<u8> = (0..300).map(|_| vip.next_byte()).collect();
+        assert!(bytes.windows(3).all(|w| w[0] != w[1] || w[1] != w[2]));
+        let mut distinct = bytes.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() > 100, "{} distinct bytes", distinct.len());
+    }
+    assert_eq!("vip".parse(), Ok(RngKind::CosmacVip));
+}
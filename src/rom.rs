@@ -1,14 +1,27 @@
 use std::fs::File;
-use std::io::{Read};
+use std::io::Read;
 
-pub fn read_rom(file_path: String) -> Vec<u8> {
-    let mut file = File::open(file_path).expect("Error opening file");
+use crate::error::Chip8Error;
+
+// FNV-1a, identifies a ROM in movie files
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn read_rom(file_path: String) -> Result<Vec<u8>, Chip8Error> {
+    let read_err = |err: std::io::Error| Chip8Error::RomRead {
+        path: file_path.clone(),
+        reason: err.to_string(),
+    };
+    let mut file = File::open(&file_path).map_err(read_err)?;
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).expect("Error reading file");
+    file.read_to_end(&mut buffer).map_err(read_err)?;
 
     let mut instrs = Vec::new();
     for byte in buffer {
         instrs.push(byte);
     }
-    instrs
+    Ok(instrs)
 }
@@ -0,0 +1,225 @@
+use std::fmt;
+
+use crate::quirks::{Platform, Quirks, PLATFORMS};
+
+// Save state layout, all numbers little endian:
+//   "C8SS", version: u16, then the machine as written by `Chip8::save_state`
+// Bump STATE_VERSION whenever anything written changes, older states are
+// refused instead of being misread.
+pub const STATE_MAGIC: &[u8; 4] = b"C8SS";
+pub const STATE_VERSION: u16 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    // doesn't start with the magic bytes
+    NotAState,
+    // written by another version of the emulator
+    UnsupportedVersion { version: u16 },
+    // ends before the machine does
+    Truncated,
+    // a value that no machine can be in
+    Invalid(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a chip8-rs save state"),
+            StateError::UnsupportedVersion { version } => write!(
+                f,
+                "save state version {} is not supported, this build reads version {}",
+                version, STATE_VERSION
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(reason) => write!(f, "corrupt save state: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub(crate) struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new() -> StateWriter {
+        let mut buf = STATE_MAGIC.to_vec();
+        buf.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        StateWriter { buf }
+    }
+
+    pub(crate) fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub(crate) fn bool(&mut self, val: bool) {
+        self.buf.push(val as u8);
+    }
+
+    pub(crate) fn u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    // length prefixed
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn platform(&mut self, platform: Platform) {
+        let index = PLATFORMS.iter().position(|p| *p == platform).unwrap();
+        self.u8(index as u8);
+    }
+
+    pub(crate) fn quirks(&mut self, quirks: &Quirks) {
+        self.u8(quirks.to_bits());
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    // checks the header
+    pub(crate) fn new(data: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        if !data.starts_with(STATE_MAGIC) {
+            return Err(StateError::NotAState);
+        }
+        let mut reader = StateReader {
+            data,
+            pos: STATE_MAGIC.len(),
+        };
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion { version });
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(StateError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            val => Err(StateError::Invalid(format!("{} is not a bool", val))),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn platform(&mut self) -> Result<Platform, StateError> {
+        let index = self.u8()?;
+        PLATFORMS
+            .get(index as usize)
+            .copied()
+            .ok_or(StateError::Invalid(format!("unknown platform {}", index)))
+    }
+
+    pub(crate) fn quirks(&mut self) -> Result<Quirks, StateError> {
+        Ok(Quirks::from_bits(self.u8()?))
+    }
+
+    // nothing may be left over
+    pub(crate) fn finish(self) -> Result<(), StateError> {
+        if self.pos != self.data.len() {
+            return Err(StateError::Invalid(format!(
+                "{} unexpected trailing bytes",
+                self.data.len() - self.pos
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_save_state_round_trip() {
+    use crate::cpu::Chip8;
+
+    // LD V0, 0x2a; CALL 0x206; JP 0x204; HIGH; RET
+    let rom = [0x60, 0x2A, 0x22, 0x06, 0x12, 0x04, 0x00, 0xFF, 0x00, 0xEE];
+    let mut chip8 = Chip8::with_platform(Platform::SuperChip);
+    chip8.load_to_ram(&rom).unwrap();
+    for _ in 0..3 {
+        chip8.cpu_loop().unwrap();
+    }
+    chip8.sound_timer.set_time_left(7);
+    let state = chip8.save_state();
+
+    let mut other = Chip8::new();
+    other.load_state(&state).unwrap();
+    assert_eq!(other.get_platform(), Platform::SuperChip);
+    assert_eq!(other.get_quirks(), chip8.get_quirks());
+    assert_eq!(other.get_reg(0), 0x2A);
+    assert_eq!(other.get_pc(), 0x208);
+    assert!(other.is_hires());
+    assert_eq!(other.display.num_cols, 128);
+    assert_eq!(other.sound_timer.get_time_left(), 7);
+    assert_eq!(other.save_state(), state);
+    // returns from the call like the original would
+    other.cpu_loop().unwrap();
+    assert_eq!(other.get_pc(), 0x204);
+
+    let mut old = state.clone();
+    old[4] = 0;
+    assert_eq!(
+        other.load_state(&old),
+        Err(StateError::UnsupportedVersion { version: 0 })
+    );
+    assert_eq!(
+        other.load_state(&state[..state.len() - 1]),
+        Err(StateError::Truncated)
+    );
+    assert_eq!(other.load_state(b"nope"), Err(StateError::NotAState));
+
+    // displays no machine can have
+    for (rows, cols) in [(0, 0), (32, 0), (0, 64), (16, 8)] {
+        let mut w = StateWriter::new();
+        crate::display::Framebuffer::new(rows, cols).write_state(&mut w);
+        let data = w.finish();
+        let mut r = StateReader::new(&data).unwrap();
+        let display = crate::display::Framebuffer::read_state(&mut r);
+        assert!(matches!(display, Err(StateError::Invalid(_))));
+    }
+}
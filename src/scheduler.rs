@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use crate::cpu::Chip8;
+use crate::error::Chip8Error;
+
+// the delay/sound timers (and the display refresh) run at 60 Hz
+pub const FRAME_RATE: u32 = 60;
+pub const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+
+pub const DEFAULT_IPS: u32 = 700;
+// never try to make up for more than this much lost host time at once, so a
+// long stall doesn't turn into a burst of fast forward
+const MAX_CATCH_UP_FRAMES: u32 = 15;
+
+// Runs the CPU at a fixed number of instructions per second of emulated time
+// and ticks the timers exactly 60 times per emulated second. Host time is fed
+// in through `advance` and consumed in whole frames (fixed timestep), so
+// emulation speed doesn't depend on how often the frontend calls in.
+pub struct Scheduler {
+    ips: u32,
+    // leftover host time that hasn't made up a whole frame yet
+    accumulator: Duration,
+    // instructions owed from ips not dividing evenly into frames, in 1/60ths
+    instr_carry: u32,
+    // instructions left in the frame being single stepped through
+    step_instrs_left: Option<u32>,
+}
+
+impl Scheduler {
+    pub fn new(ips: u32) -> Scheduler {
+        Scheduler {
+            ips,
+            accumulator: Duration::ZERO,
+            instr_carry: 0,
+            step_instrs_left: None,
+        }
+    }
+
+    pub fn with_instrs_per_frame(instrs_per_frame: u32) -> Scheduler {
+        Scheduler::new(instrs_per_frame * FRAME_RATE)
+    }
+
+    pub fn get_ips(&self) -> u32 {
+        self.ips
+    }
+
+    pub fn set_ips(&mut self, ips: u32) {
+        self.ips = ips;
+        self.instr_carry = 0;
+    }
+
+    // run one 60 Hz frame worth of instructions, then tick the timers. A
+    // fault that doesn't halt the machine still lets the frame finish, the
+    // first one is returned afterwards
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        let owed = self.ips + self.instr_carry;
+        self.instr_carry = owed % FRAME_RATE;
+        let mut result = Ok(());
+        for _ in 0..owed / FRAME_RATE {
+            if let Err(err) = chip8.cpu_loop() {
+                if chip8.is_halted() {
+                    return Err(err);
+                }
+                result = result.and(Err(err));
+            }
+        }
+        chip8.tick_timers();
+        result
+    }
+
+    // Run a single instruction, ticking the timers once a frame's worth have
+    // run (for the debugger). Returns whether the timers ticked.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<bool, Chip8Error> {
+        let left = self.step_instrs_left.get_or_insert_with(|| {
+            let owed = self.ips + self.instr_carry;
+            self.instr_carry = owed % FRAME_RATE;
+            owed / FRAME_RATE
+        });
+        if *left > 0 {
+            *left -= 1;
+            chip8.cpu_loop()?;
+        }
+        if *left > 0 {
+            return Ok(false);
+        }
+        chip8.tick_timers();
+        self.step_instrs_left = None;
+        Ok(true)
+    }
+
+    // feed in elapsed host time and run however many whole frames it covers,
+    // returns the number of frames run
+    pub fn advance(&mut self, chip8: &mut Chip8, elapsed: Duration) -> Result<u32, Chip8Error> {
+        self.advance_with(chip8, elapsed, |_| {}, |_| {})
+    }
+
+    // `advance`, calling `before_frame` at the start of every frame so input
+    // can be fed in on frame boundaries (movie recording and playback), and
+    // `after_frame` once it has run (rewind snapshots)
+    pub fn advance_with<F, G>(
+        &mut self,
+        chip8: &mut Chip8,
+        elapsed: Duration,
+        mut before_frame: F,
+        mut after_frame: G,
+    ) -> Result<u32, Chip8Error>
+    where
+        F: FnMut(&mut Chip8),
+        G: FnMut(&Chip8),
+    {
+        self.accumulator += elapsed;
+        let mut frames = 0;
+        while self.accumulator >= FRAME_TIME {
+            if frames == MAX_CATCH_UP_FRAMES {
+                // too far behind, drop the rest instead of racing to catch up
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+            self.accumulator -= FRAME_TIME;
+            before_frame(chip8);
+            self.run_frame(chip8)?;
+            after_frame(chip8);
+            frames += 1;
+        }
+        Ok(frames)
+    }
+
+    // how long the host can sleep before the next frame is due
+    pub fn time_until_next_frame(&self) -> Duration {
+        FRAME_TIME.saturating_sub(self.accumulator)
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_IPS)
+    }
+}
+
+#[test]
+fn test_scheduler_rates() {
+    // V0 += 1; jump back to 0x200
+    let mut chip8 = Chip8::new();
+    chip8.load_to_ram(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    chip8.delay_timer.set_time_left(100);
+
+    let mut scheduler = Scheduler::new(120);
+    let mut frames = 0;
+    for _ in 0..60 {
+        frames += scheduler.advance(&mut chip8, FRAME_TIME).unwrap();
+    }
+    assert_eq!(frames, 60);
+    // 120 instructions in one emulated second = 60 loop iterations
+    assert_eq!(chip8.get_reg(0), 60);
+    assert_eq!(chip8.delay_timer.get_time_left(), 40);
+
+    // a long host stall only catches up a bounded number of frames, each one
+    // seen by the callbacks
+    let (mut before, mut after) = (0, 0);
+    assert_eq!(
+        scheduler.advance_with(
+            &mut chip8,
+            Duration::from_secs(5),
+            |_| before += 1,
+            |_| after += 1
+        ),
+        Ok(MAX_CATCH_UP_FRAMES)
+    );
+    assert_eq!((before, after), (MAX_CATCH_UP_FRAMES, MAX_CATCH_UP_FRAMES));
+}
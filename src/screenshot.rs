@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::display::Framebuffer;
+use crate::video::Palette;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+// biggest stored (uncompressed) deflate block
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = crc >> 1 ^ 0xEDB88320 & mask;
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut crc_data = kind.to_vec();
+    crc_data.extend_from_slice(data);
+    out.write_all(&crc32(&crc_data).to_be_bytes())
+}
+
+// zlib stream of stored deflate blocks, the screen is small enough that
+// compressing isn't worth an encoder
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// Indexed PNG, 2 bits per pixel with the palette's four colors (one per
+// plane mask), each CHIP-8 pixel drawn as a scale x scale square.
+pub fn write_png<W: Write>(
+    framebuffer: &Framebuffer,
+    palette: &Palette,
+    scale: u32,
+    mut out: W,
+) -> io::Result<()> {
+    let scale = scale.max(1) as usize;
+    let width = framebuffer.num_cols * scale;
+    let height = framebuffer.num_rows * scale;
+    out.write_all(&PNG_SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth 2, indexed color, deflate, no filter, not interlaced
+    header.extend_from_slice(&[2, 3, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header)?;
+
+    let plte: Vec<u8> = palette
+        .colors
+        .iter()
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect();
+    write_chunk(&mut out, b"PLTE", &plte)?;
+
+    // each scanline: filter type 0, then 4 pixels per byte, leftmost first
+    let row_bytes = width.div_ceil(4);
+    let mut pixels = Vec::with_capacity((row_bytes + 1) * height);
+    for row in framebuffer.buffer() {
+        let mut line = vec![0u8; row_bytes];
+        for x in 0..width {
+            let cell = row[x / scale] & 0b11;
+            line[x / 4] |= cell << (6 - 2 * (x % 4));
+        }
+        for _ in 0..scale {
+            pixels.push(0);
+            pixels.extend_from_slice(&line);
+        }
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&pixels))?;
+    write_chunk(&mut out, b"IEND", &[])?;
+    out.flush()
+}
+
+// Binary (P4) 1-bit PBM at one pixel per CHIP-8 pixel, a pixel lit in any
+// plane is black.
+pub fn write_pbm<W: Write>(framebuffer: &Framebuffer, mut out: W) -> io::Result<()> {
+    write!(
+        out,
+        "P4\n{} {}\n",
+        framebuffer.num_cols, framebuffer.num_rows
+    )?;
+    let row_bytes = framebuffer.num_cols.div_ceil(8);
+    for row in framebuffer.buffer() {
+        let mut line = vec![0u8; row_bytes];
+        for (x, cell) in row.iter().enumerate() {
+            if *cell != 0 {
+                line[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        out.write_all(&line)?;
+    }
+    out.flush()
+}
+
+// PBM for a .pbm path, PNG for anything else
+pub fn save(
+    framebuffer: &Framebuffer,
+    palette: &Palette,
+    scale: u32,
+    path: &Path,
+) -> io::Result<()> {
+    let out = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("pbm") => write_pbm(framebuffer, out),
+        _ => write_png(framebuffer, palette, scale, out),
+    }
+}
+
+#[test]
+fn test_screenshot_formats() {
+    assert_eq!(crc32(b"IEND"), 0xAE426082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+    let mut fb = Framebuffer::new(2, 9);
+    fb.set_display(0, 0, 1);
+    fb.set_display(1, 8, 3);
+    let mut pbm = Vec::new();
+    write_pbm(&fb, &mut pbm).unwrap();
+    assert_eq!(pbm, b"P4\n9 2\n\x80\x00\x00\x80");
+
+    let mut png = Vec::new();
+    write_png(&fb, &Palette::default(), 2, &mut png).unwrap();
+    assert_eq!(&png[..8], &PNG_SIGNATURE);
+    // IHDR: 18x4
+    assert_eq!(&png[16..24], &[0, 0, 0, 18, 0, 0, 0, 4]);
+    // first scanline after the zlib and stored block headers: filter byte,
+    // then pixels 0-1 (plane 1) in the top bits
+    let idat = png.windows(4).position(|w| w == b"IDAT").unwrap() + 4;
+    assert_eq!(&png[idat + 7..idat + 9], &[0, 0b0101_0000]);
+}
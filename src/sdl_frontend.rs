@@ -0,0 +1,486 @@
+use crate::cli::{self, Options};
+use chip8_rs::audio::{AudioSettings, AudioSink, SoundFrame, Synth};
+use chip8_rs::capture::{self, Capture};
+use chip8_rs::display::Framebuffer;
+use chip8_rs::key_input::KeyEvent;
+use chip8_rs::movie::{Movie, MoviePlayer, MovieRecorder};
+use chip8_rs::rewind::Rewind;
+use chip8_rs::scheduler::{Scheduler, FRAME_RATE, FRAME_TIME};
+use chip8_rs::screenshot;
+use chip8_rs::video::{RgbaBuffer, VideoSink};
+use chip8_rs::{rom, Chip8, NUM_COLS, NUM_ROWS};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use std::path::Path;
+use std::time::Instant;
+
+// size of one CHIP-8 pixel on screen
+const SCALE: u32 = 10;
+
+// uploads the framebuffer as a streaming texture and lets SDL scale it up
+// to the window, instead of filling one rect per pixel
+struct SdlVideo<'a> {
+    canvas: Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    // kept between frames, replaced when the resolution changes
+    texture: Option<Texture<'a>>,
+    rgba: RgbaBuffer,
+}
+
+impl<'a> SdlVideo<'a> {
+    fn new(
+        mut canvas: Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+    ) -> SdlVideo<'a> {
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.present();
+        SdlVideo {
+            canvas,
+            texture_creator,
+            texture: None,
+            rgba: RgbaBuffer::new(),
+        }
+    }
+
+    fn draw(&mut self) -> Result<(), String> {
+        let (width, height) = (self.rgba.width() as u32, self.rgba.height() as u32);
+        // lores/hires switch
+        if let Some(texture) = &self.texture {
+            let query = texture.query();
+            if (query.width, query.height) != (width, height) {
+                self.texture = None;
+            }
+        }
+        let texture = match &mut self.texture {
+            Some(texture) => texture,
+            empty => empty.insert(
+                self.texture_creator
+                    .create_texture_streaming(PixelFormatEnum::RGBA32, width, height)
+                    .map_err(|err| err.to_string())?,
+            ),
+        };
+        texture
+            .update(None, self.rgba.pixels(), width as usize * 4)
+            .map_err(|err| err.to_string())?;
+        self.canvas.clear();
+        self.canvas.copy(texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+impl VideoSink for SdlVideo<'_> {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        self.rgba.present(framebuffer);
+        // the frame is dropped, the next present tries again
+        if let Err(err) = self.draw() {
+            eprintln!("Error: drawing the screen: {}", err);
+        }
+    }
+}
+
+const SAMPLE_RATE: i32 = 44100;
+// small device buffer (~12 ms) so a beep starts right after its tick
+const AUDIO_BUFFER_SAMPLES: u16 = 512;
+// don't let more than this many frames of sound queue up, e.g. while
+// catching up after a stall, or the buzzer lags behind the picture
+const MAX_QUEUED_FRAMES: u32 = 4;
+
+// renders each tick's sound straight into an SDL audio queue
+struct SdlAudio {
+    queue: AudioQueue<f32>,
+    synth: Synth,
+    samples: Vec<f32>,
+}
+
+impl SdlAudio {
+    fn new(sdl_context: &sdl2::Sdl, settings: AudioSettings) -> Result<SdlAudio, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(AUDIO_BUFFER_SAMPLES),
+        };
+        let queue = audio_subsystem.open_queue::<f32, _>(None, &desired)?;
+        let synth = Synth::new(settings, queue.spec().freq as u32);
+        queue.resume();
+        Ok(SdlAudio {
+            queue,
+            synth,
+            samples: Vec::new(),
+        })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn tick(&mut self, frame: &SoundFrame) {
+        self.samples.clear();
+        self.synth.render(frame, &mut self.samples);
+        // silence needs no queueing, the device plays it on underrun
+        let frame_bytes = self.synth.get_sample_rate() / FRAME_RATE * 4;
+        if !frame.on || self.queue.size() > MAX_QUEUED_FRAMES * frame_bytes {
+            return;
+        }
+        if let Err(err) = self.queue.queue_audio(&self.samples) {
+            eprintln!("Error: audio: {}", err);
+        }
+    }
+}
+
+pub fn chip8_keycode_map(kc: Keycode) -> Option<u8> {
+    match kc {
+        Keycode::Num0 => Some(0x0),
+        Keycode::Num1 => Some(0x1),
+        Keycode::Num2 => Some(0x2),
+        Keycode::Num3 => Some(0x3),
+        Keycode::Num4 => Some(0x4),
+        Keycode::Num5 => Some(0x5),
+        Keycode::Num6 => Some(0x6),
+        Keycode::Num7 => Some(0x7),
+        Keycode::Num8 => Some(0x8),
+        Keycode::Num9 => Some(0x9),
+        Keycode::A => Some(0xa),
+        Keycode::B => Some(0xb),
+        Keycode::C => Some(0xc),
+        Keycode::D => Some(0xd),
+        Keycode::E => Some(0xe),
+        Keycode::F => Some(0xf),
+        _ => None,
+    }
+}
+
+// F1-F9 pick a save state slot
+fn state_slot(kc: Keycode) -> Option<u8> {
+    let slots = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+        Keycode::F9,
+    ];
+    slots.iter().position(|k| *k == kc).map(|i| i as u8 + 1)
+}
+
+// states live next to the ROM, e.g. `game.ch8.state1`
+fn state_path(rom_path: &str, slot: u8) -> String {
+    format!("{}.state{}", rom_path, slot)
+}
+
+fn save_slot(cpu: &Chip8, rom_path: &str, slot: u8) {
+    let path = state_path(rom_path, slot);
+    match std::fs::write(&path, cpu.save_state()) {
+        Ok(()) => eprintln!("Saved state to {}", path),
+        Err(err) => eprintln!("Error: {}: {}", path, err),
+    }
+}
+
+fn load_slot(cpu: &mut Chip8, rom_path: &str, slot: u8) {
+    let path = state_path(rom_path, slot);
+    let loaded = std::fs::read(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|data| cpu.load_state(&data).map_err(|err| err.to_string()));
+    match loaded {
+        Ok(()) => eprintln!("Loaded state from {}", path),
+        Err(err) => eprintln!("Error: {}: {}", path, err),
+    }
+}
+
+// F12 saves a PNG as the window shows it, Shift+F12 a 1-bit PBM, next to
+// the ROM as `game.ch8.shot1.png`, `game.ch8.shot2.png`...
+fn save_screenshot(framebuffer: &Framebuffer, video: &SdlVideo, rom_path: &str, pbm: bool) {
+    let ext = if pbm { "pbm" } else { "png" };
+    let path = (1..)
+        .map(|n| format!("{}.shot{}.{}", rom_path, n, ext))
+        .find(|path| !Path::new(path).exists())
+        .unwrap();
+    let palette = video.rgba.get_palette();
+    match screenshot::save(framebuffer, &palette, SCALE, Path::new(&path)) {
+        Ok(()) => eprintln!("Saved screenshot to {}", path),
+        Err(err) => eprintln!("Error: {}: {}", path, err),
+    }
+}
+
+// F10 starts recording every frame to `game.ch8.cap1.gif`,
+// `game.ch8.cap2.gif`... and stops it again
+fn start_capture(video: &SdlVideo, path: &str) -> Option<Box<dyn Capture>> {
+    // same size as the window
+    match capture::open(path, &video.rgba.get_palette(), SCALE / 2) {
+        Ok(recording) => {
+            eprintln!("Recording to {}", path);
+            Some(recording)
+        }
+        Err(err) => {
+            eprintln!("Error: {}: {}", path, err);
+            None
+        }
+    }
+}
+
+fn next_capture_path(rom_path: &str) -> String {
+    (1..)
+        .map(|n| format!("{}.cap{}.gif", rom_path, n))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+fn stop_capture(recording: Box<dyn Capture>, path: &str) {
+    match recording.close() {
+        Ok(()) => eprintln!("Saved recording to {}", path),
+        Err(err) => eprintln!("Error: {}: {}", path, err),
+    }
+}
+
+// the machine, plus a movie recorder or player when asked for one
+fn build_chip8(
+    options: &Options,
+) -> Result<(Chip8, Option<MovieRecorder>, Option<MoviePlayer>), String> {
+    if let Some(path) = &options.play {
+        let movie = cli::read_movie(path)?;
+        let cpu = options.build_movie_chip8(&movie)?;
+        return Ok((cpu, None, Some(MoviePlayer::new(movie))));
+    }
+    let cpu = options.build_chip8()?;
+    let recorder = match &options.record {
+        Some(_) => {
+            let rom =
+                rom::read_rom(options.rom_path.clone()).map_err(|err| format!("Error: {}", err))?;
+            Some(MovieRecorder::new(Movie::new(&rom, &cpu, options.ips)))
+        }
+        None => None,
+    };
+    Ok((cpu, recorder, None))
+}
+
+fn save_movie(recorder: &MovieRecorder, path: &str) {
+    let written = std::fs::File::create(path)
+        .and_then(|file| recorder.movie().write(std::io::BufWriter::new(file)));
+    match written {
+        Ok(()) => eprintln!(
+            "Recorded {} frames to {}",
+            recorder.movie().frames.len(),
+            path
+        ),
+        Err(err) => eprintln!("Error: {}: {}", path, err),
+    }
+}
+
+pub fn run(options: &Options) {
+    let sdl_context = sdl2::init().unwrap();
+    let vid_subsystem = sdl_context.video().unwrap();
+
+    let window = vid_subsystem
+        .window(
+            "Chip8 Emulator",
+            NUM_COLS as u32 * SCALE,
+            NUM_ROWS as u32 * SCALE,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
+
+    let canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut video = SdlVideo::new(canvas, &texture_creator);
+    video.rgba.set_palette(options.palette);
+
+    let (mut cpu, mut recorder, mut player) = match build_chip8(options) {
+        Ok(built) => built,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // no sound is better than no emulator
+    match SdlAudio::new(&sdl_context, options.audio) {
+        Ok(audio) => cpu.set_audio_sink(Box::new(audio)),
+        Err(err) => eprintln!("Warning: no audio: {}", err),
+    }
+
+    // -- DEBUG (only shows up with --trace cpu=trace)
+    cpu.inspect_ram();
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    // a movie plays back at the speed it was recorded at
+    let ips = player.as_ref().map_or(options.ips, |p| p.movie().ips);
+    let mut scheduler = Scheduler::new(ips);
+    let mut last_time = Instant::now();
+    let mut rewind = Rewind::new(options.rewind_frames, options.rewind_bytes);
+    let mut rewinding = false;
+    // stopped by a break watchpoint until P is pressed
+    let mut paused = false;
+    let mut capture_path = options.capture.clone();
+    let mut recording = capture_path
+        .as_deref()
+        .and_then(|path| start_capture(&video, path));
+
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'running,
+                // Shift+F1-F9 saves to a slot, F1-F9 loads it back
+                Event::KeyDown {
+                    keycode: Some(kc),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if state_slot(kc).is_some() => {
+                    let slot = state_slot(kc).unwrap();
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        save_slot(&cpu, &options.rom_path, slot);
+                    } else if recorder.is_some() || player.is_some() {
+                        // the movie would no longer match what's on screen
+                        eprintln!("Can't load a state while a movie is recording or playing");
+                    } else {
+                        load_slot(&mut cpu, &options.rom_path, slot);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } if paused => {
+                    eprintln!("Resumed");
+                    paused = false;
+                }
+                // next palette, redrawn right away even while paused
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => {
+                    let palette = video.rgba.get_palette().next_preset();
+                    video.rgba.set_palette(palette);
+                    video.present(&cpu.display);
+                    eprintln!("Palette: {}", palette);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => {
+                    let pbm = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    save_screenshot(&cpu.display, &video, &options.rom_path, pbm);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => match (recording.take(), capture_path.take()) {
+                    (Some(old), Some(path)) => stop_capture(old, &path),
+                    _ => {
+                        let path = next_capture_path(&options.rom_path);
+                        recording = start_capture(&video, &path);
+                        capture_path = Some(path);
+                    }
+                },
+                // run time backwards while backspace is held
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = recorder.is_none() && player.is_none(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                // queue keypad changes, held keys auto-repeat so skip those
+                Event::KeyDown {
+                    keycode: Some(kc),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(k) = chip8_keycode_map(kc) {
+                        feed_key(&mut cpu, &mut recorder, &player, KeyEvent::Down(k));
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(kc), ..
+                } => {
+                    if let Some(k) = chip8_keycode_map(kc) {
+                        feed_key(&mut cpu, &mut recorder, &player, KeyEvent::Up(k));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // run as many 60 Hz frames as the host time since the last pass covers
+        let now = Instant::now();
+        if rewinding {
+            // one frame back per frame, the scheduler carries on from there
+            // once the key is let go
+            rewind.step_back(&mut cpu);
+        } else if !cpu.is_halted() && !paused {
+            // movie input goes in on frame boundaries, where the screen of
+            // the frame before is also recorded
+            let before_frame = |cpu: &mut Chip8| {
+                if let Some(recording) = &mut recording {
+                    recording.present(&cpu.display);
+                }
+                if let Some(recorder) = &mut recorder {
+                    recorder.start_frame(cpu);
+                } else if let Some(player) = &mut player {
+                    player.start_frame(cpu);
+                }
+            };
+            // one rewind snapshot per emulated frame, however many ran
+            let after_frame = |cpu: &Chip8| rewind.push(cpu);
+            match scheduler.advance_with(&mut cpu, now - last_time, before_frame, after_frame) {
+                Ok(_) => {}
+                // keep the window up so the last frame can still be inspected
+                Err(err) => eprintln!("Error: {}", err),
+            }
+        }
+        last_time = now;
+        if let Some(hit) = cpu.take_watch_hit() {
+            eprintln!("Stopped by watchpoint: {} (P resumes)", hit);
+            paused = true;
+        }
+        // 00FD (SUPER-CHIP exit) closes the window
+        if cpu.has_exited() {
+            break 'running;
+        }
+
+        // redraw display if anything was drawn since the last present
+        if cpu.display.display_update() {
+            video.present(&cpu.display);
+        }
+        if rewinding {
+            ::std::thread::sleep(FRAME_TIME);
+        } else {
+            ::std::thread::sleep(scheduler.time_until_next_frame());
+        }
+    }
+
+    if let (Some(recorder), Some(path)) = (&recorder, &options.record) {
+        save_movie(recorder, path);
+    }
+    if let (Some(recording), Some(path)) = (recording, &capture_path) {
+        stop_capture(recording, path);
+    }
+}
+
+// live keypad input, which a movie being recorded takes over and a movie
+// being played back shuts out until it's over
+fn feed_key(
+    cpu: &mut Chip8,
+    recorder: &mut Option<MovieRecorder>,
+    player: &Option<MoviePlayer>,
+    event: KeyEvent,
+) {
+    if let Some(recorder) = recorder {
+        recorder.key_event(event);
+    } else if player.as_ref().is_none_or(MoviePlayer::is_finished) {
+        cpu.key_input.push_event(event);
+    }
+}
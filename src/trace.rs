@@ -0,0 +1,187 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Cpu,
+    Display,
+    Input,
+    Timers,
+}
+
+pub const CATEGORIES: [Category; 4] = [
+    Category::Cpu,
+    Category::Display,
+    Category::Input,
+    Category::Timers,
+];
+
+impl Category {
+    fn name(&self) -> &'static str {
+        match self {
+            Category::Cpu => "cpu",
+            Category::Display => "display",
+            Category::Input => "input",
+            Category::Timers => "timers",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Level::Off),
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown trace level {}", s)),
+        }
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CATEGORIES
+            .into_iter()
+            .find(|cat| cat.name() == s)
+            .ok_or(format!("unknown trace category {}", s))
+    }
+}
+
+// one line of the per-instruction trace, the machine state right before the
+// instruction at pc runs
+pub struct InstrRecord<'a> {
+    pub pc: u16,
+    pub opcode: u16,
+    pub v_regs: &'a [u8; 16],
+    pub i_reg: u16,
+    pub delay_timer: usize,
+    pub sound_timer: usize,
+}
+
+// Leveled log messages filtered per category, plus an optional JSON lines
+// trace of every executed instruction. Everything is off by default.
+pub struct Tracer {
+    levels: [Level; CATEGORIES.len()],
+    instr_trace: Option<Box<dyn Write>>,
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        Tracer {
+            levels: [Level::Off; CATEGORIES.len()],
+            instr_trace: None,
+        }
+    }
+
+    pub fn set_level(&mut self, cat: Category, level: Level) {
+        self.levels[cat as usize] = level;
+    }
+
+    // apply a filter like "debug" (every category) or "cpu=trace,input=info"
+    pub fn set_filter(&mut self, filter: &str) -> Result<(), String> {
+        for part in filter.split(',').filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                Some((cat, level)) => self.set_level(cat.parse()?, level.parse()?),
+                None => {
+                    let level = part.parse()?;
+                    for cat in CATEGORIES {
+                        self.set_level(cat, level);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn enabled(&self, cat: Category, level: Level) -> bool {
+        level != Level::Off && level <= self.levels[cat as usize]
+    }
+
+    // use the `trace!` macro instead so disabled messages are never formatted
+    pub fn log(&self, cat: Category, level: Level, args: fmt::Arguments) {
+        eprintln!("[{} {:?}] {}", cat.name(), level, args);
+    }
+
+    pub fn set_instr_trace(&mut self, writer: Box<dyn Write>) {
+        self.instr_trace = Some(writer);
+    }
+
+    pub fn instr_trace_enabled(&self) -> bool {
+        self.instr_trace.is_some()
+    }
+
+    pub fn trace_instr(&mut self, record: &InstrRecord) {
+        if let Some(writer) = &mut self.instr_trace {
+            let v_regs = record.v_regs.map(|v| v.to_string()).join(",");
+            let written = writeln!(
+                writer,
+                "{{\"pc\":{},\"opcode\":{},\"v\":[{}],\"i\":{},\"dt\":{},\"st\":{}}}",
+                record.pc,
+                record.opcode,
+                v_regs,
+                record.i_reg,
+                record.delay_timer,
+                record.sound_timer
+            );
+            if let Err(err) = written {
+                // stop tracing instead of failing every instruction
+                self.instr_trace = None;
+                self.log(
+                    Category::Cpu,
+                    Level::Error,
+                    format_args!("instruction trace disabled: {}", err),
+                );
+            }
+        }
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// trace!(chip8.tracer, Cpu, Debug, "V{} = {}", x, val)
+macro_rules! trace {
+    ($tracer:expr, $cat:ident, $level:ident, $($arg:tt)+) => {
+        if $tracer.enabled($crate::trace::Category::$cat, $crate::trace::Level::$level) {
+            $tracer.log(
+                $crate::trace::Category::$cat,
+                $crate::trace::Level::$level,
+                format_args!($($arg)+),
+            );
+        }
+    };
+}
+pub(crate) use trace;
+
+#[test]
+fn test_trace_filter() {
+    let mut tracer = Tracer::new();
+    assert!(!tracer.enabled(Category::Cpu, Level::Error));
+    tracer.set_filter("info,cpu=trace,timers=off").unwrap();
+    assert!(tracer.enabled(Category::Cpu, Level::Trace));
+    assert!(tracer.enabled(Category::Input, Level::Info));
+    assert!(!tracer.enabled(Category::Input, Level::Debug));
+    assert!(!tracer.enabled(Category::Timers, Level::Error));
+    assert!(tracer.set_filter("gpu=debug").is_err());
+}
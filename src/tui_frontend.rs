@@ -0,0 +1,269 @@
+use crate::cli::Options;
+use chip8_rs::display::Framebuffer;
+use chip8_rs::instr::Instruction;
+use chip8_rs::key_input::KeyTimeouts;
+use chip8_rs::scheduler::Scheduler;
+use chip8_rs::video::Palette;
+use chip8_rs::Chip8;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Colors, Print, ResetColor, SetColors};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+// columns between the screen and the register panel
+const PANEL_GAP: usize = 2;
+
+// raw mode and the alternate screen for as long as it lives, so the terminal
+// is given back even when the emulator panics
+struct TerminalGuard {
+    // key releases are reported (kitty keyboard protocol)
+    releases: bool,
+}
+
+impl TerminalGuard {
+    fn new() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(TerminalGuard { releases })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.releases {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn rgb(color: [u8; 4]) -> Color {
+    Color::Rgb {
+        r: color[0],
+        g: color[1],
+        b: color[2],
+    }
+}
+
+// Two CHIP-8 rows per text row: '▀' in the top pixel's color on the bottom
+// pixel's color, so XO-CHIP planes keep their colors too.
+fn screen_lines(framebuffer: &Framebuffer, palette: &Palette) -> Vec<Vec<u8>> {
+    let rows = framebuffer.buffer();
+    let mut lines = Vec::with_capacity(rows.len().div_ceil(2));
+    for pair in rows.chunks(2) {
+        let mut line = Vec::new();
+        let mut colors = None;
+        for (c, &top) in pair[0].iter().enumerate() {
+            let bottom = pair.get(1).map_or(0, |row| row[c]);
+            let cell = Colors::new(rgb(palette.color(top)), rgb(palette.color(bottom)));
+            // only switch colors when they change
+            if colors != Some(cell) {
+                let _ = queue!(line, SetColors(cell));
+                colors = Some(cell);
+            }
+            let _ = queue!(line, Print('▀'));
+        }
+        let _ = queue!(line, ResetColor);
+        lines.push(line);
+    }
+    lines
+}
+
+// registers, timers and the next instruction
+fn panel_lines(cpu: &Chip8, status: &str) -> Vec<String> {
+    let pc = cpu.get_pc();
+    let instr = match cpu.peek_word(pc) {
+        Ok(word) => match Instruction::decode(word) {
+            Ok(instr) => format!("{:04x}  {}", word, instr),
+            Err(_) => format!("{:04x}  (data)", word),
+        },
+        Err(_) => "(out of memory)".to_string(),
+    };
+    let mut lines = vec![
+        format!("PC {:03x}  {}", pc, instr),
+        format!(
+            "I  {:03x}  SP {}",
+            cpu.get_index_reg(),
+            cpu.get_stack().len()
+        ),
+        format!(
+            "DT {:02x}   ST {:02x}",
+            cpu.delay_timer.get_time_left(),
+            cpu.sound_timer.get_time_left()
+        ),
+    ];
+    for row in 0..4 {
+        let regs: Vec<String> = (0..4)
+            .map(|col| row * 4 + col)
+            .map(|reg| format!("V{:X} {:02x}", reg, cpu.get_reg(reg)))
+            .collect();
+        lines.push(regs.join("  "));
+    }
+    let keys: String = (0..16)
+        .map(|key| match cpu.key_input.is_pressed(key) {
+            true => char::from_digit(key as u32, 16).unwrap(),
+            false => '.',
+        })
+        .collect();
+    lines.push(format!("keys {}", keys));
+    lines.push(String::new());
+    lines.push(status.to_string());
+    lines.push("0-9 a-f keypad, Tab palette, Esc quits".to_string());
+    lines
+}
+
+// Redraws only the terminal lines that changed since the last frame, over
+// SSH a full redraw every frame would be too much.
+struct Screen {
+    drawn: Vec<Vec<u8>>,
+}
+
+impl Screen {
+    fn draw(&mut self, out: &mut impl Write, cpu: &Chip8, palette: &Palette, status: &str) {
+        let screen = screen_lines(&cpu.display, palette);
+        let panel = panel_lines(cpu, status);
+        let lines: Vec<Vec<u8>> = (0..screen.len().max(panel.len()))
+            .map(|row| {
+                let mut line = match screen.get(row) {
+                    Some(line) => line.clone(),
+                    None => " ".repeat(cpu.display.num_cols).into_bytes(),
+                };
+                line.extend(" ".repeat(PANEL_GAP).bytes());
+                line.extend(panel.get(row).map_or("", String::as_str).bytes());
+                line
+            })
+            .collect();
+        // the resolution changed, start over
+        if lines.len() != self.drawn.len() {
+            let _ = queue!(out, Clear(ClearType::All));
+            self.drawn.clear();
+        }
+        for (row, line) in lines.iter().enumerate() {
+            if self.drawn.get(row) != Some(line) {
+                let _ = queue!(out, MoveTo(0, row as u16));
+                let _ = out.write_all(line);
+                let _ = queue!(out, Clear(ClearType::UntilNewLine));
+            }
+        }
+        let _ = out.flush();
+        self.drawn = lines;
+    }
+
+    // after a resize the terminal may have dropped what was on it
+    fn invalidate(&mut self) {
+        self.drawn.clear();
+    }
+}
+
+// hex digits are the keypad, same as in the window
+fn keypad_key(code: KeyCode) -> Option<u8> {
+    match code {
+        KeyCode::Char(c) => c.to_digit(16).map(|key| key as u8),
+        _ => None,
+    }
+}
+
+pub fn run(options: &Options) -> Result<(), String> {
+    let mut cpu = options.build_chip8()?;
+    let guard = TerminalGuard::new().map_err(|err| format!("Error: terminal: {}", err))?;
+    let mut out = io::BufWriter::new(io::stdout());
+    let mut keys = KeyTimeouts::new(options.key_timeout);
+    let mut scheduler = Scheduler::new(options.ips);
+    let mut screen = Screen { drawn: Vec::new() };
+    let mut palette = options.palette;
+    let mut last_time = Instant::now();
+    // stopped by a break watchpoint until P is pressed
+    let mut paused = false;
+    let mut status = String::new();
+
+    'running: loop {
+        // wait for keys until the next frame is due
+        let mut timeout = scheduler.time_until_next_frame();
+        while event::poll(timeout).map_err(|err| format!("Error: terminal: {}", err))? {
+            timeout = Duration::ZERO;
+            let event = event::read().map_err(|err| format!("Error: terminal: {}", err))?;
+            let key = match event {
+                Event::Key(key) => key,
+                Event::Resize(..) => {
+                    let _ = queue!(out, Clear(ClearType::All));
+                    screen.invalidate();
+                    continue;
+                }
+                _ => continue,
+            };
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match (key.code, key.kind) {
+                (KeyCode::Esc, _) => break 'running,
+                // raw mode swallows the signal
+                (KeyCode::Char('c'), _) if ctrl => break 'running,
+                // the next draw picks it up, the machine carries on
+                (KeyCode::Tab, KeyEventKind::Press) => {
+                    palette = palette.next_preset();
+                    status = format!("Palette: {}", palette);
+                }
+                (KeyCode::Char('p'), KeyEventKind::Press) if paused => {
+                    paused = false;
+                    status = "Resumed".to_string();
+                }
+                (code, kind) => {
+                    let Some(k) = keypad_key(code) else {
+                        continue;
+                    };
+                    let event = match kind {
+                        KeyEventKind::Release => keys.release(k),
+                        _ => keys.press(k, Instant::now()),
+                    };
+                    if let Some(event) = event {
+                        cpu.key_input.push_event(event);
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        // with releases reported there's nothing to time out
+        if !guard.releases {
+            for event in keys.expire(now) {
+                cpu.key_input.push_event(event);
+            }
+        }
+        if !cpu.is_halted() && !paused {
+            if let Err(err) = scheduler.advance(&mut cpu, now - last_time) {
+                // keep the screen up so the last frame can still be inspected
+                status = format!("Error: {}", err);
+            }
+        }
+        last_time = now;
+        if let Some(hit) = cpu.take_watch_hit() {
+            status = format!("Stopped by watchpoint: {} (P resumes)", hit);
+            paused = true;
+        }
+        if cpu.is_halted() && status.is_empty() {
+            status = "Halted".to_string();
+        }
+        // 00FD (SUPER-CHIP exit) quits
+        if cpu.has_exited() {
+            break 'running;
+        }
+        screen.draw(&mut out, &cpu, &palette, &status);
+    }
+    drop(out);
+    drop(guard);
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::display::Framebuffer;
+
+// colors used when turning the framebuffer into pixels
+pub const OFF_COLOR: [u8; 4] = [0, 0, 0, 255];
+pub const ON_COLOR: [u8; 4] = [255, 255, 255, 255];
+// XO-CHIP: only plane 2 lit, and both planes lit
+pub const PLANE_2_COLOR: [u8; 4] = [170, 170, 170, 255];
+pub const BOTH_PLANES_COLOR: [u8; 4] = [85, 85, 85, 255];
+
+// indexed by the cell's plane mask
+pub const COLORS: [[u8; 4]; 4] = [OFF_COLOR, ON_COLOR, PLANE_2_COLOR, BOTH_PLANES_COLOR];
+
+// The colors a framebuffer is drawn with, `COLORS` unless changed. Written
+// (and parsed) as a preset name or 2 or 4 hex colors, e.g. `amber` or
+// `#000000,#ffffff,#aaaaaa,#555555`: off, plane 1, plane 2, both planes.
+// With only off and on given, the XO-CHIP plane colors are shades between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 4]; 4],
+}
+
+const fn rgb(hex: u32) -> [u8; 4] {
+    [(hex >> 16) as u8, (hex >> 8) as u8, hex as u8, 255]
+}
+
+// named palettes, in the order hotkeys cycle through them
+pub const PALETTES: [(&str, Palette); 6] = [
+    ("classic", Palette { colors: COLORS }),
+    (
+        "amber",
+        Palette {
+            colors: [rgb(0x140c00), rgb(0xffb000), rgb(0xa87400), rgb(0x5c3f00)],
+        },
+    ),
+    (
+        "green",
+        Palette {
+            colors: [rgb(0x061206), rgb(0x33ff66), rgb(0x22a844), rgb(0x135c26)],
+        },
+    ),
+    // the original Game Boy's greenish LCD, dark pixels on a light panel,
+    // every shade clearly apart from the panel
+    (
+        "lcd",
+        Palette {
+            colors: [rgb(0x9bbc0f), rgb(0x0f380f), rgb(0x306230), rgb(0x6b8c14)],
+        },
+    ),
+    (
+        "high-contrast",
+        Palette {
+            colors: [rgb(0x000000), rgb(0xffffff), rgb(0xffff00), rgb(0x00ffff)],
+        },
+    ),
+    // Okabe-Ito colors, told apart with any kind of color blindness
+    (
+        "colorblind",
+        Palette {
+            colors: [rgb(0x000000), rgb(0xffffff), rgb(0xe69f00), rgb(0x56b4e9)],
+        },
+    ),
+];
+
+impl Palette {
+    pub fn color(&self, cell: u8) -> [u8; 4] {
+        self.colors[cell as usize & 0b11]
+    }
+
+    pub fn preset(name: &str) -> Option<Palette> {
+        PALETTES
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|(_, palette)| *palette)
+    }
+
+    // the preset's name, if this is one
+    pub fn name(&self) -> Option<&'static str> {
+        PALETTES
+            .iter()
+            .find(|(_, palette)| palette == self)
+            .map(|(name, _)| *name)
+    }
+
+    // the preset after this one, custom palettes go back to the first
+    pub fn next_preset(&self) -> Palette {
+        let next = match PALETTES.iter().position(|(_, palette)| palette == self) {
+            Some(i) => (i + 1) % PALETTES.len(),
+            None => 0,
+        };
+        PALETTES[next].1
+    }
+}
+
+fn mix(from: [u8; 4], to: [u8; 4], thirds: u16) -> [u8; 4] {
+    let channel = |i: usize| ((from[i] as u16 * (3 - thirds) + to[i] as u16 * thirds) / 3) as u8;
+    [channel(0), channel(1), channel(2), 255]
+}
+
+fn parse_color(hex: &str) -> Result<[u8; 4], String> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    // from_str_radix alone would take a leading '+'
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("bad color {} (expected rrggbb)", hex));
+    }
+    u32::from_str_radix(digits, 16)
+        .map(rgb)
+        .map_err(|err| err.to_string())
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = self.name() {
+            return write!(f, "{}", name);
+        }
+        let colors: Vec<String> = self
+            .colors
+            .iter()
+            .map(|c| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]))
+            .collect();
+        write!(f, "{}", colors.join(","))
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(palette) = Palette::preset(s.trim()) {
+            return Ok(palette);
+        }
+        let specs: Vec<&str> = s
+            .split([',', ' ', '\t', '\n'])
+            .filter(|color| !color.is_empty())
+            .collect();
+        if specs.len() != 2 && specs.len() != 4 {
+            let names: Vec<&str> = PALETTES.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "expected one of {} or 2 or 4 hex colors",
+                names.join(", ")
+            ));
+        }
+        let colors = specs
+            .into_iter()
+            .map(parse_color)
+            .collect::<Result<Vec<_>, _>>()?;
+        let colors = match colors[..] {
+            [off, on] => [off, on, mix(off, on, 2), mix(off, on, 1)],
+            [off, on, plane_2, both] => [off, on, plane_2, both],
+            _ => unreachable!(),
+        };
+        Ok(Palette { colors })
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette { colors: COLORS }
+    }
+}
+
+// anything that can show a framebuffer (a window, a texture, a file...)
+pub trait VideoSink {
+    fn present(&mut self, framebuffer: &Framebuffer);
+}
+
+// drops every frame, for running headless
+pub struct NullSink;
+
+impl VideoSink for NullSink {
+    fn present(&mut self, _framebuffer: &Framebuffer) {}
+}
+
+// keeps the last presented frame as tightly packed RGBA8 pixels, row major
+pub struct RgbaBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    palette: Palette,
+}
+
+impl RgbaBuffer {
+    pub fn new() -> RgbaBuffer {
+        RgbaBuffer {
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+            palette: Palette::default(),
+        }
+    }
+
+    pub fn get_palette(&self) -> Palette {
+        self.palette
+    }
+
+    // takes effect from the next present
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+impl Default for RgbaBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VideoSink for RgbaBuffer {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        self.width = framebuffer.num_cols;
+        self.height = framebuffer.num_rows;
+        self.pixels.clear();
+        for row in framebuffer.buffer() {
+            for &cell in row {
+                self.pixels.extend_from_slice(&self.palette.color(cell));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rgba_buffer_present() {
+    let mut fb = Framebuffer::new(2, 3);
+    fb.set_display(1, 2, 1);
+    let mut rgba = RgbaBuffer::new();
+    rgba.present(&fb);
+    assert_eq!((rgba.width(), rgba.height()), (3, 2));
+    assert_eq!(rgba.pixels().len(), 2 * 3 * 4);
+    assert_eq!(&rgba.pixels()[..4], &OFF_COLOR);
+    assert_eq!(&rgba.pixels()[20..], &ON_COLOR);
+}
+
+#[test]
+fn test_palette_specs() {
+    let amber: Palette = "Amber".parse().unwrap();
+    assert_eq!(amber.to_string(), "amber");
+    assert_eq!(amber.next_preset().to_string(), "green");
+    // off and on only: the plane colors are mixed in between, as in `COLORS`
+    let gray: Palette = "#000000, ffffff".parse().unwrap();
+    assert_eq!(gray, Palette::default());
+    let custom: Palette = "#102030,#ffffff,#ff0000,#00ff00".parse().unwrap();
+    assert_eq!(custom.color(2), [255, 0, 0, 255]);
+    assert_eq!(custom.to_string(), "#102030,#ffffff,#ff0000,#00ff00");
+    assert_eq!(custom.next_preset(), Palette::default());
+    assert!("#000000".parse::<Palette>().is_err());
+    assert!("sepia".parse::<Palette>().is_err());
+    assert!("#00000g,#ffffff".parse::<Palette>().is_err());
+    assert!("+fffff,#ffffff".parse::<Palette>().is_err());
+}
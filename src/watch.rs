@@ -0,0 +1,253 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::instr::parse_number;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    // fetched as (part of) an instruction
+    Execute,
+}
+
+impl AccessKind {
+    fn name(&self) -> &'static str {
+        match self {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+            AccessKind::Execute => "execute",
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        match self {
+            AccessKind::Read => 0b001,
+            AccessKind::Write => 0b010,
+            AccessKind::Execute => 0b100,
+        }
+    }
+}
+
+// one byte of memory touched by the running program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    pub kind: AccessKind,
+    pub addr: u16,
+    // the byte read, or the one written
+    pub value: u8,
+    // instruction that made the access
+    pub pc: u16,
+}
+
+impl fmt::Display for MemAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:03x} = {:02x} at pc {:03x}",
+            self.kind.name(),
+            self.addr,
+            self.value,
+            self.pc
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WatchAction {
+    // stop the machine before the next instruction
+    #[default]
+    Break,
+    // report the access and keep going
+    Log,
+}
+
+// Watches an address range for some kinds of access. Written (and parsed)
+// as `<addr>[-<end>][:<access>][:log]`, e.g. `0x300-0x302:w:log`, where
+// access is any of r, w and x (default rw).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    // inclusive
+    pub end: u16,
+    // AccessKind bits
+    kinds: u8,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, kinds: &[AccessKind], action: WatchAction) -> Watchpoint {
+        Watchpoint {
+            start,
+            end,
+            kinds: kinds.iter().fold(0, |bits, kind| bits | kind.bit()),
+            action,
+        }
+    }
+
+    pub fn matches(&self, access: &MemAccess) -> bool {
+        (self.start..=self.end).contains(&access.addr) && self.kinds & access.kind.bit() != 0
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03x}", self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:03x}", self.end)?;
+        }
+        let kinds: String = [
+            (AccessKind::Read, 'r'),
+            (AccessKind::Write, 'w'),
+            (AccessKind::Execute, 'x'),
+        ]
+        .iter()
+        .filter(|(kind, _)| self.kinds & kind.bit() != 0)
+        .map(|(_, letter)| letter)
+        .collect();
+        write!(f, ":{}", kinds)?;
+        if self.action == WatchAction::Log {
+            write!(f, ":log")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let range = parts.next().unwrap_or_default();
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+            None => {
+                let addr = parse_number(range)?;
+                (addr, addr)
+            }
+        };
+        if end < start {
+            return Err(format!("range {} ends before it starts", range));
+        }
+        let mut kinds = Vec::new();
+        let mut action = WatchAction::Break;
+        for part in parts {
+            match part {
+                "log" => action = WatchAction::Log,
+                "break" => action = WatchAction::Break,
+                letters => {
+                    for letter in letters.chars() {
+                        kinds.push(match letter {
+                            'r' => AccessKind::Read,
+                            'w' => AccessKind::Write,
+                            'x' => AccessKind::Execute,
+                            _ => return Err(format!("unknown access {} (r, w, x)", letter)),
+                        });
+                    }
+                }
+            }
+        }
+        if kinds.is_empty() {
+            kinds = vec![AccessKind::Read, AccessKind::Write];
+        }
+        Ok(Watchpoint::new(start, end, &kinds, action))
+    }
+}
+
+// The watchpoints a machine checks every access against. Log hits are
+// written to stderr unless given another writer.
+pub struct Watchpoints {
+    points: Vec<Watchpoint>,
+    log: Option<Box<dyn Write>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            points: Vec::new(),
+            log: None,
+        }
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        if !self.points.contains(&watchpoint) {
+            self.points.push(watchpoint);
+        }
+    }
+
+    // removes every watchpoint covering addr, returns how many there were
+    pub fn remove(&mut self, addr: u16) -> usize {
+        let before = self.points.len();
+        self.points
+            .retain(|point| !(point.start..=point.end).contains(&addr));
+        before - self.points.len()
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn set_log(&mut self, writer: Box<dyn Write>) {
+        self.log = Some(writer);
+    }
+
+    // logs the access if a log watchpoint covers it, returns whether a
+    // break watchpoint does
+    pub fn check(&mut self, access: &MemAccess) -> bool {
+        let mut hit_break = false;
+        let mut hit_log = false;
+        for point in self.points.iter().filter(|point| point.matches(access)) {
+            match point.action {
+                WatchAction::Break => hit_break = true,
+                WatchAction::Log => hit_log = true,
+            }
+        }
+        if hit_log {
+            match &mut self.log {
+                Some(writer) => {
+                    // a broken log shouldn't stop the machine
+                    let _ = writeln!(writer, "watch: {}", access);
+                }
+                None => eprintln!("watch: {}", access),
+            }
+        }
+        hit_break
+    }
+}
+
+impl Default for Watchpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_watchpoints() {
+    use crate::cpu::Chip8;
+
+    let spec: Watchpoint = "0x300-0x302:w:log".parse().unwrap();
+    assert_eq!(spec.to_string(), "300-302:w:log");
+    assert_eq!("0x300".parse::<Watchpoint>().unwrap().to_string(), "300:rw");
+    assert!("0x302-0x300".parse::<Watchpoint>().is_err());
+    assert!("0x300:q".parse::<Watchpoint>().is_err());
+
+    // LD I, 0x301; LD V0, 0x2a; LD [I], V0; ADD V0, 1
+    let rom = [0xA3, 0x01, 0x60, 0x2A, 0xF0, 0x55, 0x70, 0x01];
+    let mut chip8 = Chip8::new();
+    chip8.load_to_ram(&rom).unwrap();
+    chip8.watchpoints.add("0x300-0x302:w".parse().unwrap());
+    for _ in 0..4 {
+        chip8.cpu_loop().unwrap();
+    }
+    // stopped after the store, the ADD hasn't run
+    let hit = chip8.take_watch_hit().unwrap();
+    assert_eq!(hit.to_string(), "write 301 = 2a at pc 204");
+    assert_eq!(chip8.get_reg(0), 0x2A);
+    chip8.cpu_loop().unwrap();
+    assert_eq!(chip8.get_reg(0), 0x2B);
+}